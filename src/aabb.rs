@@ -1,8 +1,7 @@
-use crate::hittable::Hittable;
 use crate::interval::*;
 use crate::ray::*;
 use crate::vec3::*;
-use std::ops::{Add, Div, Index, IndexMut, Mul, MulAssign, Neg, Sub};
+use std::ops::Add;
 
 #[derive(Copy, Clone, Default)]
 pub struct Aabb {
@@ -88,6 +87,7 @@ impl Aabb {
         true
     }
 
+    #[allow(clippy::self_named_constructors)]
     pub fn aabb(box0: Aabb, box1: Aabb) -> Aabb {
         Self {
             x: Interval::new_from_intervals(box0.x, box1.x),
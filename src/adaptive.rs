@@ -0,0 +1,101 @@
+use crate::color::{luminance, Color};
+
+/**
+ * Settings for adaptive sampling: every pixel first takes `min_samples`, then
+ * keeps taking batches of `min_samples` more until the estimated relative
+ * error of its mean drops below `threshold` or it reaches `max_samples`.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveSampling {
+    pub min_samples: i32,
+    pub max_samples: i32,
+    pub threshold: f64,
+}
+
+impl AdaptiveSampling {
+    pub fn new(min_samples: i32, max_samples: i32, threshold: f64) -> Self {
+        let min_samples = min_samples.max(2);
+        Self {
+            min_samples,
+            max_samples: max_samples.max(min_samples),
+            threshold,
+        }
+    }
+}
+
+/**
+ * Running mean and variance of the luminance of a pixel's samples, using
+ * Welford's algorithm.
+ */
+#[derive(Clone, Copy, Default)]
+pub struct PixelStatistics {
+    pub(crate) count: u32,
+    pub(crate) mean: f64,
+    pub(crate) m2: f64,
+}
+
+impl PixelStatistics {
+    pub fn add(&mut self, sample: Color) {
+        let x = luminance(sample);
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            return 0.;
+        }
+        self.m2 / (self.count - 1) as f64
+    }
+
+    /**
+     * Standard error of the mean relative to the mean. Very dark pixels are
+     * measured against a small floor instead, so they do not sample forever.
+     */
+    pub fn relative_error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+
+        let standard_error = (self.variance() / self.count as f64).sqrt();
+        standard_error / self.mean.max(1e-3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixel_statistics() {
+        let mut stats = PixelStatistics::default();
+        assert_eq!(stats.relative_error(), f64::INFINITY);
+
+        for value in [1., 2., 3., 4.] {
+            stats.add(Color::new(value, value, value));
+        }
+
+        assert_eq!(stats.count(), 4);
+        assert!((stats.mean() - 2.5).abs() < 1e-12);
+        assert!((stats.variance() - 5. / 3.).abs() < 1e-12);
+        assert!((stats.relative_error() - (5f64 / 12.).sqrt() / 2.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_new_keeps_max_above_min() {
+        let adaptive = AdaptiveSampling::new(1, 1, 0.01);
+        assert_eq!((adaptive.min_samples, adaptive.max_samples), (2, 2));
+        let adaptive = AdaptiveSampling::new(16, 4, 0.01);
+        assert_eq!((adaptive.min_samples, adaptive.max_samples), (16, 16));
+    }
+}
@@ -0,0 +1,451 @@
+use crate::bvh::BvhNode;
+use crate::camera::{Camera, CameraError};
+use crate::framebuffer::Framebuffer;
+use crate::hittable::Hittable;
+use crate::hittable_list::HittableList;
+use crate::mix_seed;
+use crate::motion::CameraKeyframe;
+use crate::output::save;
+use crate::scheduler::RenderControl;
+use crate::vec3::Point3;
+use image::error::{ParameterError, ParameterErrorKind};
+use image::{ImageError, ImageResult};
+use std::ops::{Add, Mul, RangeInclusive, Sub};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/**
+ * How a track moves from one keyframe to the next.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    /// A cubic Bezier curve whose handles follow the neighbouring keyframes,
+    /// so the motion passes smoothly through every keyframe. It eases in and
+    /// out at the first and last one.
+    Bezier,
+}
+
+/**
+ * A value that can be interpolated: colors, points, offsets and numbers.
+ */
+pub trait Animatable:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f64, Output = Self>
+{
+}
+
+impl<T> Animatable for T where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T> {}
+
+#[derive(Clone, Copy)]
+struct Key<T> {
+    time: f64,
+    value: T,
+    interpolation: Interpolation,
+}
+
+/**
+ * A value changing over time, given by keyframes in seconds. Before the first
+ * and after the last keyframe the track holds their values.
+ */
+#[derive(Clone)]
+pub struct Track<T> {
+    keys: Vec<Key<T>>,
+}
+
+impl<T: Animatable> Track<T> {
+    pub fn new() -> Self {
+        Self { keys: Vec::new() }
+    }
+
+    pub fn constant(value: T) -> Self {
+        Self::new().key(0., value, Interpolation::Linear)
+    }
+
+    /**
+     * Adds a keyframe, with `interpolation` used on the way to the next one.
+     * A keyframe at the same time as an existing one replaces it.
+     */
+    pub fn key(mut self, time: f64, value: T, interpolation: Interpolation) -> Self {
+        let key = Key {
+            time,
+            value,
+            interpolation,
+        };
+        let k = self.keys.partition_point(|key| key.time < time);
+        if k < self.keys.len() && self.keys[k].time == time {
+            self.keys[k] = key;
+        } else {
+            self.keys.insert(k, key);
+        }
+
+        self
+    }
+
+    pub fn sample(&self, time: f64) -> T {
+        assert!(!self.keys.is_empty(), "track has no keyframes");
+
+        let next = self.keys.partition_point(|key| key.time <= time);
+        if next == 0 {
+            return self.keys[0].value;
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].value;
+        }
+
+        let (a, b) = (self.keys[next - 1], self.keys[next]);
+        let duration = b.time - a.time;
+        let t = (time - a.time) / duration;
+
+        match a.interpolation {
+            Interpolation::Linear => a.value + (b.value - a.value) * t,
+            Interpolation::Bezier => {
+                let p1 = a.value + self.slope(next - 1) * (duration / 3.);
+                let p2 = b.value - self.slope(next) * (duration / 3.);
+                let s = 1. - t;
+
+                a.value * (s * s * s)
+                    + p1 * (3. * s * s * t)
+                    + p2 * (3. * s * t * t)
+                    + b.value * (t * t * t)
+            }
+        }
+    }
+
+    /**
+     * The rate of change at keyframe `k`, pointing from the previous keyframe
+     * to the next one, and zero at the ends.
+     */
+    fn slope(&self, k: usize) -> T {
+        let zero = self.keys[k].value * 0.;
+        if k == 0 || k + 1 == self.keys.len() {
+            return zero;
+        }
+
+        let (prev, next) = (self.keys[k - 1], self.keys[k + 1]);
+        (next.value - prev.value) * (1. / (next.time - prev.time))
+    }
+}
+
+impl<T: Animatable> Default for Track<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/**
+ * Tracks for the camera parameters. Parameters without a track keep the value
+ * set on the camera.
+ */
+#[derive(Clone, Default)]
+pub struct CameraTracks {
+    pub lookfrom: Option<Track<Point3>>,
+    pub lookat: Option<Track<Point3>>,
+    pub vfov: Option<Track<f64>>,
+    pub defocus_angle: Option<Track<f64>>,
+    pub focus_dist: Option<Track<f64>>,
+}
+
+impl CameraTracks {
+    /**
+     * Poses the camera at `time`. When it moves, the camera also gets
+     * keyframes at the opening and closing of its shutter, a fraction of
+     * `frame_duration` later, so the motion within the frame is blurred.
+     * Fails if the tracks take the camera somewhere it cannot render from.
+     */
+    pub fn apply(
+        &self,
+        camera: &mut Camera,
+        time: f64,
+        frame_duration: f64,
+    ) -> Result<(), CameraError> {
+        let pose = |time: f64| {
+            CameraKeyframe::new(
+                time,
+                self.lookfrom
+                    .as_ref()
+                    .map_or(camera.lookfrom, |track| track.sample(time)),
+                self.lookat
+                    .as_ref()
+                    .map_or(camera.lookat, |track| track.sample(time)),
+                self.vfov
+                    .as_ref()
+                    .map_or(camera.vfov, |track| track.sample(time)),
+            )
+        };
+
+        let current = pose(time);
+        if self.lookfrom.is_some() || self.lookat.is_some() || self.vfov.is_some() {
+            let (open, close) = (camera.shutter.open, camera.shutter.close);
+            let keyframe = |shutter_time: f64| CameraKeyframe {
+                time: shutter_time,
+                ..pose(time + shutter_time * frame_duration)
+            };
+            camera.keyframes = vec![keyframe(open), keyframe(close)];
+        }
+        camera.lookfrom = current.lookfrom;
+        camera.lookat = current.lookat;
+        camera.vfov = current.vfov;
+
+        if let Some(track) = &self.defocus_angle {
+            camera.defocus_angle = track.sample(time);
+        }
+        if let Some(track) = &self.focus_dist {
+            camera.focus_dist = track.sample(time);
+        }
+
+        camera.validate()?;
+        camera.initialize();
+
+        Ok(())
+    }
+}
+
+/**
+ * A sequence of frames. The static part of the scene is put in a BVH once and
+ * shared by every frame, while `animated` builds the objects that change for
+ * a given time in seconds, typically by sampling tracks for `Translate`
+ * offsets, `RotateY` angles and material colors.
+ */
+pub struct Animation {
+    pub camera: Camera,
+    pub camera_tracks: CameraTracks,
+    pub frame_rate: f64,
+    static_world: Option<Arc<dyn Hittable>>,
+    animated: Box<dyn Fn(f64) -> HittableList>,
+}
+
+impl Animation {
+    pub fn new<F>(camera: Camera, static_world: HittableList, animated: F) -> Self
+    where
+        F: Fn(f64) -> HittableList + 'static,
+    {
+        let static_world =
+            (!static_world.objects.is_empty()).then(|| Arc::from(BvhNode::new(static_world)));
+
+        Self {
+            camera,
+            camera_tracks: CameraTracks::default(),
+            frame_rate: 24.,
+            static_world,
+            animated: Box::new(animated),
+        }
+    }
+
+    pub fn time(&self, frame: u32) -> f64 {
+        frame as f64 / self.frame_rate
+    }
+
+    /**
+     * The scene at `time`: the shared static BVH next to a new one over the
+     * animated objects.
+     */
+    pub fn world(&self, time: f64) -> HittableList {
+        let mut world = HittableList::default();
+        if let Some(static_world) = &self.static_world {
+            world.add(Box::new(static_world.clone()));
+        }
+
+        let animated = (self.animated)(time);
+        if !animated.objects.is_empty() {
+            world.add(BvhNode::new(animated));
+        }
+
+        world
+    }
+
+    /**
+     * The camera for `frame`, posed by the tracks. Each frame gets its own
+     * seed so the noise does not stay fixed on the screen.
+     */
+    pub fn camera(&self, frame: u32) -> Result<Camera, CameraError> {
+        let mut camera = self.camera.clone();
+        self.camera_tracks
+            .apply(&mut camera, self.time(frame), 1. / self.frame_rate)?;
+        camera.seed = mix_seed(self.camera.seed, frame as u64);
+
+        Ok(camera)
+    }
+
+    pub fn render_frame(
+        &self,
+        frame: u32,
+        control: &RenderControl,
+    ) -> Result<Framebuffer, CameraError> {
+        let camera = self.camera(frame)?;
+        let world = self.world(self.time(frame));
+
+        Ok(camera.render_with(&world, control))
+    }
+
+    /**
+     * Renders the frames one after the other and saves each to the file
+     * given by `frame_path`. Stops after the current frame once `control` is
+     * cancelled.
+     */
+    pub fn render_frames(
+        &self,
+        frames: RangeInclusive<u32>,
+        pattern: &str,
+        control: &RenderControl,
+    ) -> ImageResult<()> {
+        for frame in frames {
+            if control.cancellation.is_cancelled() {
+                break;
+            }
+
+            let image = self.render_frame(frame, control).map_err(|error| {
+                ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::Generic(
+                    format!("frame {}: {}", frame, error),
+                )))
+            })?;
+            let path = frame_path(pattern, frame);
+            if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                std::fs::create_dir_all(parent).map_err(ImageError::IoError)?;
+            }
+            save(path, &image)?;
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * Numbers a file for `frame` by replacing the last run of `#` in `pattern`
+ * with the frame number, padded with zeros to its length, as in
+ * "frames/shot_####.png". Without any `#` the number is appended to the file
+ * name before the extension.
+ */
+pub fn frame_path(pattern: &str, frame: u32) -> PathBuf {
+    match pattern.rfind('#') {
+        Some(end) => {
+            let start = pattern[..end].trim_end_matches('#').len();
+            let width = end + 1 - start;
+            PathBuf::from(format!(
+                "{}{:0width$}{}",
+                &pattern[..start],
+                frame,
+                &pattern[end + 1..]
+            ))
+        }
+        None => {
+            let path = PathBuf::from(pattern);
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let name = match path.extension() {
+                Some(extension) => format!("{}_{:04}.{}", stem, frame, extension.to_string_lossy()),
+                None => format!("{}_{:04}", stem, frame),
+            };
+            path.with_file_name(name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::hittable::Translate;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::vec3::Vec3;
+    use std::path::Path;
+
+    #[test]
+    fn test_track_interpolation() {
+        let linear =
+            Track::new()
+                .key(1., 0., Interpolation::Linear)
+                .key(3., 4., Interpolation::Linear);
+        assert_eq!(linear.sample(0.), 0.);
+        assert_eq!(linear.sample(2.), 2.);
+        assert_eq!(linear.sample(5.), 4.);
+
+        let bezier = Track::new()
+            .key(0., 0., Interpolation::Bezier)
+            .key(1., 1., Interpolation::Bezier)
+            .key(2., 0., Interpolation::Bezier);
+        assert_eq!(bezier.sample(1.), 1.);
+        // Eases out of the first keyframe and is flat at the peak.
+        assert!(bezier.sample(0.1) < 0.1);
+        assert!((bezier.sample(0.99) - bezier.sample(1.01)).abs() < 1e-9);
+        assert!((bezier.sample(0.5) - 0.5).abs() < 0.1);
+
+        let colors = Track::constant(Color::new(1., 0., 0.)).key(
+            2.,
+            Color::new(0., 0., 1.),
+            Interpolation::Linear,
+        );
+        assert!(colors.sample(1.) == Color::new(0.5, 0., 0.5));
+    }
+
+    #[test]
+    fn test_frame_path() {
+        assert_eq!(
+            frame_path("out/shot_####.png", 7),
+            Path::new("out/shot_0007.png")
+        );
+        assert_eq!(frame_path("f#.exr", 12), Path::new("f12.exr"));
+        assert_eq!(
+            frame_path("out/shot.png", 3),
+            Path::new("out/shot_0003.png")
+        );
+    }
+
+    #[test]
+    fn test_animation_frames() {
+        let material = Arc::new(Lambertian::new_from_color(Color::new(0.5, 0.5, 0.5)));
+        let mut static_world = HittableList::default();
+        static_world.add(Box::new(Sphere::new(
+            Point3::new(0., -2., -1.),
+            0.5,
+            material.clone(),
+        )));
+
+        let camera = Camera::builder()
+            .aspect_ratio(2.)
+            .image_width(16)
+            .samples_per_pixel(1)
+            .max_depth(2)
+            .build()
+            .unwrap();
+        let offset = Track::new()
+            .key(0., Vec3::new(-1., 0., 0.), Interpolation::Linear)
+            .key(1., Vec3::new(1., 0., 0.), Interpolation::Linear);
+        let animation = Animation::new(camera, static_world, move |time| {
+            let sphere = Box::new(Sphere::new(Point3::new(0., 0., -1.), 0.5, material.clone()));
+            HittableList::new(Box::new(Translate::new(sphere, offset.sample(time))))
+        });
+
+        let bbox = |frame| animation.world(animation.time(frame)).bounding_box();
+        assert_eq!(bbox(0).x.min, -1.5);
+        assert_eq!(bbox(12).x.max, 0.5);
+        assert_eq!(bbox(24).x.max, 1.5);
+        assert!(animation.camera(0).unwrap().seed != animation.camera(1).unwrap().seed);
+
+        let mut tracks = CameraTracks {
+            vfov: Some(Track::new().key(0., 90., Interpolation::Linear).key(
+                1.,
+                30.,
+                Interpolation::Linear,
+            )),
+            ..Default::default()
+        };
+        let mut camera = animation.camera(0).unwrap();
+        tracks.apply(&mut camera, 0.5, 0.5).unwrap();
+        assert_eq!(camera.vfov, 60.);
+        assert_eq!(camera.keyframes.len(), 2);
+        assert_eq!(camera.keyframes[1].vfov, 30.);
+
+        tracks.vfov = None;
+        let mut camera = animation.camera(0).unwrap();
+        tracks.apply(&mut camera, 0.5, 0.5).unwrap();
+        assert!(camera.keyframes.is_empty());
+
+        // Looking at the point the camera is at leaves it without a view.
+        tracks.lookat = Some(Track::constant(Point3::new(0., 0., 0.)));
+        assert_eq!(
+            tracks.apply(&mut camera, 0.5, 0.5),
+            Err(CameraError::NoViewDirection)
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use crate::color::Color;
+use crate::framebuffer::Framebuffer;
+use crate::mix_seed;
+use crate::vec3::{Point3, Vec3};
+use std::collections::HashMap;
+
+/**
+ * The auxiliary outputs a render can produce next to the radiance.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aov {
+    /// Distance of the first hit along the view axis.
+    Depth,
+    /// Shading normal at the first hit, facing the camera.
+    Normal,
+    /// World space position of the first hit.
+    Position,
+    /// Surface coordinates of the first hit.
+    Uv,
+    /// Base color of the material at the first hit.
+    Albedo,
+    /// ID of the object at the first hit, 0 for the background.
+    ObjectId,
+    /// ID of the material at the first hit, 0 for the background.
+    MaterialId,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 7] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Position,
+        Aov::Uv,
+        Aov::Albedo,
+        Aov::ObjectId,
+        Aov::MaterialId,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Position => "position",
+            Aov::Uv => "uv",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+        }
+    }
+}
+
+/**
+ * What a single camera ray sees at its first hit. The object and material
+ * keys only identify them within one render and are turned into compact IDs
+ * by `Aovs::new`.
+ */
+#[derive(Clone, Copy, Default)]
+pub struct AovSample {
+    pub depth: f64,
+    pub normal: Vec3,
+    pub position: Point3,
+    pub uv: (f64, f64),
+    pub albedo: Color,
+    pub object_key: Option<u64>,
+    pub material_key: Option<u64>,
+}
+
+/**
+ * First-hit AOVs for every pixel, stored row by row like the `Framebuffer`
+ * they belong to. Continuous values are averaged over the pixel's samples,
+ * IDs come from its first sample.
+ */
+#[derive(Clone, Default)]
+pub struct Aovs {
+    width: usize,
+    height: usize,
+    depth: Vec<f64>,
+    normal: Vec<Vec3>,
+    position: Vec<Point3>,
+    uv: Vec<(f64, f64)>,
+    albedo: Vec<Color>,
+    object_id: Vec<u32>,
+    material_id: Vec<u32>,
+}
+
+impl Aovs {
+    /**
+     * Collects one averaged sample per pixel. IDs are numbered from 1 in the
+     * order the objects and materials first appear in the image, so they stay
+     * the same between runs of the same scene.
+     */
+    pub fn new(width: usize, height: usize, pixels: Vec<AovSample>) -> Self {
+        assert_eq!(
+            pixels.len(),
+            width * height,
+            "pixel count does not match the image size"
+        );
+
+        Self {
+            width,
+            height,
+            depth: pixels.iter().map(|p| p.depth).collect(),
+            normal: pixels.iter().map(|p| p.normal).collect(),
+            position: pixels.iter().map(|p| p.position).collect(),
+            uv: pixels.iter().map(|p| p.uv).collect(),
+            albedo: pixels.iter().map(|p| p.albedo).collect(),
+            object_id: compact_ids(pixels.iter().map(|p| p.object_key)),
+            material_id: compact_ids(pixels.iter().map(|p| p.material_key)),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn depth(&self) -> &[f64] {
+        &self.depth
+    }
+
+    pub fn normal(&self) -> &[Vec3] {
+        &self.normal
+    }
+
+    pub fn position(&self) -> &[Point3] {
+        &self.position
+    }
+
+    pub fn uv(&self) -> &[(f64, f64)] {
+        &self.uv
+    }
+
+    pub fn albedo(&self) -> &[Color] {
+        &self.albedo
+    }
+
+    pub fn object_id(&self) -> &[u32] {
+        &self.object_id
+    }
+
+    pub fn material_id(&self) -> &[u32] {
+        &self.material_id
+    }
+
+    /**
+     * The AOV as an image for viewing: normals are mapped from [-1, 1] to
+     * [0, 1] and every ID gets its own color.
+     */
+    pub fn image(&self, aov: Aov) -> Framebuffer {
+        let pixels = match aov {
+            Aov::Depth => self.depth.iter().map(|&d| Color::new(d, d, d)).collect(),
+            Aov::Normal => self
+                .normal
+                .iter()
+                .map(|&n| 0.5 * (n + Vec3::new(1., 1., 1.)))
+                .collect(),
+            Aov::Position => self.position.clone(),
+            Aov::Uv => self.uv.iter().map(|&(u, v)| Color::new(u, v, 0.)).collect(),
+            Aov::Albedo => self.albedo.clone(),
+            Aov::ObjectId => self.object_id.iter().map(|&id| id_color(id)).collect(),
+            Aov::MaterialId => self.material_id.iter().map(|&id| id_color(id)).collect(),
+        };
+
+        Framebuffer::from_pixels(self.width, self.height, pixels)
+    }
+}
+
+fn compact_ids(keys: impl Iterator<Item = Option<u64>>) -> Vec<u32> {
+    let mut ids = HashMap::new();
+    keys.map(|key| match key {
+        Some(key) => {
+            let next = ids.len() as u32 + 1;
+            *ids.entry(key).or_insert(next)
+        }
+        None => 0,
+    })
+    .collect()
+}
+
+fn id_color(id: u32) -> Color {
+    if id == 0 {
+        return Color::default();
+    }
+
+    let hash = mix_seed(0, id as u64);
+    let channel = |shift: u32| ((hash >> shift) & 0xff) as f64 / 255.;
+    Color::new(channel(0), channel(8), channel(16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_follow_first_appearance() {
+        let keys = [Some(77), None, Some(5), Some(77), Some(9), Some(5)];
+        let pixels = keys
+            .iter()
+            .map(|&key| AovSample {
+                object_key: key,
+                material_key: key.map(|key| key % 2),
+                ..Default::default()
+            })
+            .collect();
+
+        let aovs = Aovs::new(3, 2, pixels);
+        assert_eq!(aovs.object_id(), [1, 0, 2, 1, 3, 2]);
+        assert_eq!(aovs.material_id(), [1, 0, 1, 1, 1, 1]);
+    }
+}
@@ -2,8 +2,9 @@ use crate::aabb::*;
 use crate::hittable::*;
 use crate::hittable_list::*;
 use crate::interval::*;
+use crate::random_int;
 use crate::ray::*;
-use ray_tracing::random_int;
+use crate::stats::{self, Phase};
 use std::cmp::Ordering;
 
 #[derive(Clone)]
@@ -14,8 +15,11 @@ pub struct BvhNode {
 }
 
 impl BvhNode {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(list: HittableList) -> Box<dyn Hittable> {
-        Self::new_split(list.objects.clone(), 0, list.objects.len())
+        stats::time(Phase::BvhBuild, || {
+            Self::new_split(list.objects.clone(), 0, list.objects.len())
+        })
     }
 
     pub fn new_split(
@@ -36,10 +40,13 @@ impl BvhNode {
         let left: Box<dyn Hittable>;
         let right: Box<dyn Hittable>;
 
+        // A single object needs no node around it, and would otherwise be
+        // found twice when collecting lights.
         if object_span == 1 {
-            left = objects[start].clone();
-            right = objects[start].clone();
-        } else if object_span == 2 {
+            return objects[start].clone();
+        }
+
+        if object_span == 2 {
             if comparator(&*objects[start], &*objects[start + 1]) == Ordering::Less {
                 left = objects[start].clone();
                 right = objects[start + 1].clone();
@@ -87,6 +94,7 @@ impl BvhNode {
 
 impl Hittable for BvhNode {
     fn hit(&self, r: Ray, mut ray_t: Interval, rec: &mut HitRecord) -> bool {
+        stats::bvh_node();
         if !self.bbox.hit(r, &mut ray_t) {
             return false;
         }
@@ -104,4 +112,9 @@ impl Hittable for BvhNode {
     fn bounding_box(&self) -> Aabb {
         self.bbox
     }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        self.left.collect_lights(lights);
+        self.right.collect_lights(lights);
+    }
 }
@@ -1,28 +1,91 @@
-use crate::color::{write_color, Color};
+use crate::adaptive::{AdaptiveSampling, PixelStatistics};
+use crate::aov::{AovSample, Aovs};
+use crate::color::Color;
+use crate::denoise::Denoiser;
+use crate::film::{Film, FilmTile};
+use crate::filter::{BoxFilter, Filter};
+use crate::framebuffer::Framebuffer;
+use crate::heatmap::Heatmap;
 use crate::hittable::{HitRecord, Hittable};
+use crate::integrator::{Integrator, IntegratorType, Scene};
 use crate::interval::Interval;
+use crate::lens::{Aperture, Lens};
+use crate::mis::LightSampling;
+use crate::motion::{CameraKeyframe, Shutter};
+use crate::progressive::{Checkpoint, ProgressiveSettings};
+use crate::projection::Projection;
 use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerType};
+use crate::scheduler::{generate_tiles, CropWindow, Progress, RenderControl, Tile, TileOrder};
+use crate::stats::{self, Phase};
+use crate::tonemap::{Clamp, DisplayTransform, ToneMapper};
 use crate::vec3::{Point3, Vec3};
-use ray_tracing::{degrees_to_radians, random_double, INFINITY};
+use crate::{degrees_to_radians, mix_seed, seed_random, INFINITY};
 use rayon::prelude::*;
+use std::error::Error;
+use std::fmt;
 use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
-#[derive(Clone, Copy, PartialEq)]
+/**
+ * Renders a world from a point of view. Built and checked by `CameraBuilder`,
+ * see `Camera::builder`.
+ */
+#[derive(Clone)]
 pub struct Camera {
-    pub aspect_ratio: f64,
-    pub image_width: i32,
-    pub samples_per_pixel: i32,
-    pub max_depth: i32,
-    pub vfov: f64,
+    pub(crate) aspect_ratio: f64,
+    pub(crate) image_width: i32,
+    pub(crate) samples_per_pixel: i32,
+    pub(crate) max_depth: i32,
+    /// Paths that bounced this often may end early, with a chance that grows
+    /// as their throughput drops. `max_depth` still caps them.
+    pub(crate) russian_roulette: Option<i32>,
+    pub(crate) vfov: f64,
+    pub(crate) projection: Projection,
 
-    pub lookfrom: Point3,
-    pub lookat: Point3,
-    pub vup: Vec3,
+    pub(crate) lookfrom: Point3,
+    pub(crate) lookat: Point3,
+    pub(crate) vup: Vec3,
 
-    pub defocus_angle: f64,
-    pub focus_dist: f64,
+    pub(crate) defocus_angle: f64,
+    pub(crate) focus_dist: f64,
+    pub(crate) lens: Lens,
+    pub(crate) shutter: Shutter,
+    /// Camera placements over the shutter interval, sorted by time. When
+    /// given they replace `lookfrom`, `lookat` and `vfov`.
+    pub(crate) keyframes: Vec<CameraKeyframe>,
+
+    pub(crate) seed: u64,
+    pub(crate) sampler: SamplerType,
+    pub(crate) filter: Arc<dyn Filter>,
+    pub(crate) adaptive: Option<AdaptiveSampling>,
+    pub(crate) tile_size: usize,
+    pub(crate) tile_order: TileOrder,
+    /// Renders only this part of the image, with the same rays as in the
+    /// full frame.
+    pub(crate) crop: Option<CropWindow>,
+    pub(crate) aovs: bool,
+    pub(crate) denoiser: Option<Denoiser>,
+    pub(crate) light_sampling: LightSampling,
+    pub(crate) integrator: IntegratorType,
+    /// Shows the work done per pixel in false color instead of radiance.
+    pub(crate) heatmap: Option<Heatmap>,
+
+    pub(crate) exposure: f64,
+    pub(crate) tone_mapper: Arc<dyn ToneMapper>,
 
     image_height: i32,
+    view: View,
+    pub(crate) background: Color,
+}
+
+/**
+ * The camera frame and viewport for one placement of the camera.
+ */
+#[derive(Clone, Copy, Default)]
+struct View {
     center: Point3,
     pixel00_loc: Point3,
     pixel_delta_u: Vec3,
@@ -32,164 +95,1964 @@ pub struct Camera {
     w: Vec3,
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
-    pub background: Color,
+}
+
+impl View {
+    fn defocus_disk_sample(&self, p: (f64, f64)) -> Point3 {
+        self.center + (p.0 * self.defocus_disk_u) + (p.1 * self.defocus_disk_v)
+    }
+}
+
+struct TileResult {
+    film_tile: FilmTile,
+    stats: Vec<PixelStatistics>,
+    samples: u64,
+}
+
+struct RenderProgress {
+    start: Instant,
+    total_tiles: usize,
+    completed_tiles: usize,
+    samples: u64,
+}
+
+impl RenderProgress {
+    fn new(total_tiles: usize) -> Self {
+        Self {
+            start: Instant::now(),
+            total_tiles,
+            completed_tiles: 0,
+            samples: 0,
+        }
+    }
+
+    fn tile_done(&mut self, samples: u64, control: &RenderControl) {
+        self.completed_tiles += 1;
+        self.samples += samples;
+
+        if let Some(callback) = &control.progress {
+            let elapsed = self.start.elapsed();
+            let remaining = self.total_tiles.saturating_sub(self.completed_tiles);
+            callback(&Progress {
+                completed_tiles: self.completed_tiles,
+                total_tiles: self.total_tiles.max(self.completed_tiles),
+                elapsed,
+                eta: Some(elapsed.mul_f64(remaining as f64 / self.completed_tiles as f64)),
+                samples_per_second: self.samples as f64 / elapsed.as_secs_f64().max(1e-9),
+            });
+        }
+    }
 }
 
 impl Camera {
-    pub fn new(
-        aspect_ratio: f64,
-        image_width: i32,
-        samples_per_pixel: i32,
-        max_depth: i32,
-    ) -> Self {
-        Camera {
-            aspect_ratio,
-            image_width,
-            samples_per_pixel,
-            max_depth,
+    pub fn builder() -> CameraBuilder {
+        CameraBuilder::default()
+    }
+
+    pub fn image_width(&self) -> usize {
+        self.image_width as usize
+    }
+
+    pub fn image_height(&self) -> usize {
+        self.image_height as usize
+    }
+
+    pub fn render(self, world: &dyn Hittable) -> Framebuffer {
+        let image = self.render_with(world, &RenderControl::stderr());
+        eprintln!("\rDone.                  \n");
+
+        image
+    }
+
+    /**
+     * Renders the image tile by tile, reporting every finished tile to the
+     * progress callback of `control`. Once `control` is cancelled the render
+     * stops early and the pixels it did not reach stay black. With a crop
+     * window only that part of the image is rendered and returned.
+     */
+    pub fn render_with(self, world: &dyn Hittable, control: &RenderControl) -> Framebuffer {
+        stats::time(Phase::Render, || self.render_film(world, control))
+    }
+
+    fn render_film(&self, world: &dyn Hittable, control: &RenderControl) -> Framebuffer {
+        let _tracing = self.heatmap.map(|_| stats::Tracing::start());
+        let width = self.image_width as usize;
+        let height = self.image_height as usize;
+        let mut film = Film::new(width, height);
+        let mut stats = vec![PixelStatistics::default(); width * height];
+
+        let tiles = self.tiles();
+        let progress = Mutex::new(RenderProgress::new(tiles.len()));
+        self.render_pass(
+            &Scene::new(world, self.background),
+            &tiles,
+            &mut film,
+            &mut stats,
+            i32::MAX,
+            self.max_samples(),
+            control,
+            &progress,
+        );
+
+        self.finish(world, &film, &stats, self.max_samples(), control)
+    }
+
+    /**
+     * Renders the crop window and pastes it into `image`, a render of the
+     * full frame, replacing the pixels it covers. Only the radiance and the
+     * sample counts are pasted: the AOVs of `image` are left as they were,
+     * since object and material IDs are numbered per render and would not
+     * match. Use `render_with` to get the AOVs of the crop window itself.
+     */
+    pub fn render_into(
+        self,
+        world: &dyn Hittable,
+        image: &mut Framebuffer,
+        control: &RenderControl,
+    ) {
+        assert_eq!(
+            (image.width(), image.height()),
+            (self.image_width as usize, self.image_height as usize),
+            "image size does not match the camera"
+        );
+
+        let crop = self.crop_region();
+        let region = self.render_with(world, control);
+        image.insert(crop.x0, crop.y0, &region);
+    }
+
+    /**
+     * Renders in passes of `settings.pass_samples` samples per pixel until
+     * every pixel has reached its sample budget, checkpointing along the way.
+     * With `settings.resume` an existing checkpoint is picked up and only the
+     * missing samples are added, so raising `samples_per_pixel` refines a
+     * finished render further. The sampler keeps the stratification of the
+     * budget the checkpoint was started with, so the added samples are not
+     * stratified with the earlier ones and a refined render is not identical
+     * to one rendered with the larger budget from the start. On cancellation
+     * the progress so far is saved.
+     */
+    pub fn render_progressive(
+        self,
+        world: &dyn Hittable,
+        settings: &ProgressiveSettings,
+        control: &RenderControl,
+    ) -> io::Result<Framebuffer> {
+        stats::time(Phase::Render, || {
+            self.render_passes(world, settings, control)
+        })
+    }
+
+    fn render_passes(
+        &self,
+        world: &dyn Hittable,
+        settings: &ProgressiveSettings,
+        control: &RenderControl,
+    ) -> io::Result<Framebuffer> {
+        let width = self.image_width as usize;
+        let height = self.image_height as usize;
+        let scene_hash = self.scene_hash(world);
+        let _tracing = self.heatmap.map(|_| stats::Tracing::start());
+
+        let mut checkpoint = match &settings.checkpoint {
+            Some(path) if settings.resume && path.exists() => {
+                let checkpoint = Checkpoint::load(path)?;
+                if (checkpoint.width(), checkpoint.height()) != (width, height)
+                    || checkpoint.seed() != self.seed
+                    || checkpoint.scene_hash() != scene_hash
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "checkpoint belongs to a different scene or camera",
+                    ));
+                }
+                checkpoint
+            }
+            _ => Checkpoint::new(width, height, self.seed, scene_hash, self.max_samples()),
+        };
+
+        let pass_samples = settings.pass_samples.max(1);
+        let tiles = self.tiles();
+        let done = tiles
+            .iter()
+            .flat_map(|tile| {
+                (tile.y0..tile.y1).flat_map(move |y| (tile.x0..tile.x1).map(move |x| y * width + x))
+            })
+            .map(|k| checkpoint.stats[k].count())
+            .min()
+            .unwrap_or(0) as i32;
+        let remaining = (self.max_samples() - done).max(0) as usize;
+        let progress = Mutex::new(RenderProgress::new(
+            tiles.len() * remaining.div_ceil(pass_samples as usize),
+        ));
+        let mut last_checkpoint = Instant::now();
+        let scene = Scene::new(world, self.background);
+        let strata = checkpoint.strata();
 
-            vfov: 0.,
-            lookfrom: Point3::new(0., 0., 0.),
-            lookat: Point3::new(0., 0., 0.),
-            vup: Vec3::new(0., 0., 0.),
+        while !control.cancellation.is_cancelled() {
+            let samples = self.render_pass(
+                &scene,
+                &tiles,
+                &mut checkpoint.film,
+                &mut checkpoint.stats,
+                pass_samples,
+                strata,
+                control,
+                &progress,
+            );
+            if samples == 0 {
+                break;
+            }
 
-            defocus_angle: 0.,
-            focus_dist: 10.,
+            if let Some(path) = &settings.checkpoint {
+                if last_checkpoint.elapsed() >= settings.checkpoint_interval {
+                    checkpoint.save(path)?;
+                    last_checkpoint = Instant::now();
+                }
+            }
+        }
+
+        if let Some(path) = &settings.checkpoint {
+            checkpoint.save(path)?;
+        }
+
+        Ok(self.finish(world, &checkpoint.film, &checkpoint.stats, strata, control))
+    }
+
+    /**
+     * Fingerprints everything the samples in a checkpoint depend on apart
+     * from the seed: the view and the sampling setup. The world is only
+     * fingerprinted by its bounding box, so edits inside it go unnoticed. The
+     * sample budget is left out so a render can be resumed with more.
+     */
+    pub fn scene_hash(&self, world: &dyn Hittable) -> u64 {
+        let bbox = world.bounding_box();
+        let values = [
+            self.aspect_ratio,
+            self.image_width as f64,
+            self.max_depth as f64,
+            self.vfov,
+            self.lookfrom[0],
+            self.lookfrom[1],
+            self.lookfrom[2],
+            self.lookat[0],
+            self.lookat[1],
+            self.lookat[2],
+            self.vup[0],
+            self.vup[1],
+            self.vup[2],
+            self.defocus_angle,
+            self.focus_dist,
+            self.filter.radius(),
+            self.background[0],
+            self.background[1],
+            self.background[2],
+            bbox.x.min,
+            bbox.x.max,
+            bbox.y.min,
+            bbox.y.max,
+            bbox.z.min,
+            bbox.z.max,
+        ];
+
+        values
+            .into_iter()
+            .chain(self.projection.parameters())
+            .chain(self.lens.parameters())
+            .chain(self.shutter.parameters())
+            .chain(self.integrator.parameters())
+            .chain(self.heatmap.map(|heatmap| heatmap as u8 as f64))
+            .chain(self.keyframes.iter().flat_map(|k| {
+                [
+                    k.time,
+                    k.lookfrom[0],
+                    k.lookfrom[1],
+                    k.lookfrom[2],
+                    k.lookat[0],
+                    k.lookat[1],
+                    k.lookat[2],
+                    k.vfov,
+                ]
+            }))
+            .fold(self.sampler as u64, |hash, value| {
+                mix_seed(hash, value.to_bits())
+            })
+    }
+
+    /**
+     * Renders all tiles once, adding up to `pass_samples` samples to every
+     * pixel that still needs them, and returns the number of samples taken.
+     * The sampler stratifies `strata` samples per pixel.
+     */
+    #[allow(clippy::too_many_arguments)]
+    fn render_pass(
+        &self,
+        scene: &Scene,
+        tiles: &[Tile],
+        film: &mut Film,
+        stats: &mut [PixelStatistics],
+        pass_samples: i32,
+        strata: i32,
+        control: &RenderControl,
+        progress: &Mutex<RenderProgress>,
+    ) -> u64 {
+        let results: Vec<Mutex<Option<TileResult>>> =
+            tiles.iter().map(|_| Mutex::new(None)).collect();
+        let next_tile = AtomicUsize::new(0);
+
+        {
+            let film = &*film;
+            let stats = &*stats;
+
+            // Every worker pulls the next tile in order, so the tile order is
+            // also the order in which the tiles get started.
+            (0..rayon::current_num_threads())
+                .into_par_iter()
+                .for_each(|_| loop {
+                    let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                    if index >= tiles.len() || control.cancellation.is_cancelled() {
+                        break;
+                    }
+
+                    let result = self.render_tile(
+                        tiles[index],
+                        scene,
+                        film,
+                        stats,
+                        pass_samples,
+                        strata,
+                        control,
+                    );
+                    let samples = result.samples;
+                    *results[index].lock().unwrap() = Some(result);
+
+                    progress.lock().unwrap().tile_done(samples, control);
+                });
+        }
 
-            image_height: 0,
-            center: Point3::new(0., 0., 0.),
-            pixel00_loc: Point3::new(0., 0., 0.),
-            pixel_delta_u: Vec3::new(0., 0., 0.),
-            pixel_delta_v: Vec3::new(0., 0., 0.),
-            u: Vec3::new(0., 0., 0.),
-            v: Vec3::new(0., 0., 0.),
-            w: Vec3::new(0., 0., 0.),
-            defocus_disk_u: Vec3::new(0., 0., 0.),
-            defocus_disk_v: Vec3::new(0., 0., 0.),
-            background: Color::default(),
+        // Merge in a fixed order so overlapping splats always add up the same.
+        let width = film.width();
+        let mut samples = 0;
+        for (tile, result) in tiles.iter().zip(results) {
+            if let Some(result) = result.into_inner().unwrap() {
+                film.merge_tile(&result.film_tile);
+                for (k, pixel_stats) in result.stats.into_iter().enumerate() {
+                    let x = tile.x0 + k % (tile.x1 - tile.x0);
+                    let y = tile.y0 + k / (tile.x1 - tile.x0);
+                    stats[y * width + x] = pixel_stats;
+                }
+                samples += result.samples;
+            }
         }
+
+        samples
     }
 
-    pub fn render(mut self, world: &dyn Hittable) {
-        self.initialize();
+    #[allow(clippy::too_many_arguments)]
+    fn render_tile(
+        &self,
+        tile: Tile,
+        scene: &Scene,
+        film: &Film,
+        stats: &[PixelStatistics],
+        pass_samples: i32,
+        strata: i32,
+        control: &RenderControl,
+    ) -> TileResult {
+        let mut film_tile = film.tile(tile.x0, tile.y0, tile.x1, tile.y1, self.filter.radius());
+        let mut sampler = self.sampler.create(strata, self.seed);
+        let integrator = self.integrator.create(self);
+        let mut tile_stats = Vec::with_capacity(tile.pixel_count());
+        let mut samples = 0;
+
+        'pixels: for j in tile.y0..tile.y1 {
+            for i in tile.x0..tile.x1 {
+                if control.cancellation.is_cancelled() {
+                    break 'pixels;
+                }
 
-        print!("P3\n{} {}\n255\n", self.image_width, self.image_height);
+                let mut pixel_stats = stats[j * film.width() + i];
+                let taken = pixel_stats.count() as i32;
+                let limit = taken.saturating_add(pass_samples).min(self.max_samples());
+                let mut target = self.min_samples().max(taken).min(limit);
 
-        let mut stdout = io::stdout().lock();
+                let (i, j) = (i as i32, j as i32);
+                loop {
+                    for sample in pixel_stats.count() as i32..target {
+                        let pixel_color = self.sample_pixel(
+                            i,
+                            j,
+                            sample,
+                            scene,
+                            &*integrator,
+                            &mut *sampler,
+                            &mut film_tile,
+                        );
+                        pixel_stats.add(pixel_color);
+                    }
 
-        let pixel_colors: Vec<Vec<Color>> = (0..self.image_height)
+                    match self.adaptive {
+                        Some(adaptive)
+                            if target < limit
+                                && pixel_stats.relative_error() > adaptive.threshold =>
+                        {
+                            target = (target + adaptive.min_samples).min(limit);
+                        }
+                        _ => break,
+                    }
+                }
+
+                samples += (pixel_stats.count() as i32 - taken) as u64;
+                tile_stats.push(pixel_stats);
+            }
+        }
+
+        // Pixels skipped by a cancellation keep their previous statistics.
+        let skipped = tile_stats.len()..tile.pixel_count();
+        tile_stats.extend(skipped.map(|k| {
+            let x = tile.x0 + k % (tile.x1 - tile.x0);
+            let y = tile.y0 + k / (tile.x1 - tile.x0);
+            stats[y * film.width() + x]
+        }));
+
+        TileResult {
+            film_tile,
+            stats: tile_stats,
+            samples,
+        }
+    }
+
+    fn finish(
+        &self,
+        world: &dyn Hittable,
+        film: &Film,
+        stats: &[PixelStatistics],
+        strata: i32,
+        control: &RenderControl,
+    ) -> Framebuffer {
+        let mut image = film.resolve();
+        if self.adaptive.is_some() {
+            image.set_sample_counts(stats.iter().map(|s| s.count()).collect());
+        }
+        if self.crop.is_some() {
+            let crop = self.crop_region();
+            image = image.crop(crop.x0, crop.y0, crop.x1, crop.y1);
+        }
+        if let Some(heatmap) = self.heatmap {
+            let scale = heatmap.colorize(&mut image);
+            image.set_heatmap_scale(scale);
+            return image;
+        }
+        // The denoiser is guided by the AOVs, so they are rendered for it
+        // even when they are not wanted in the output.
+        if self.aovs || self.denoiser.is_some() {
+            if let Some(aovs) = self.render_aovs(world, strata, control) {
+                image.set_aovs(aovs);
+            }
+        }
+        if let Some(denoiser) = &self.denoiser {
+            image = denoiser.denoise(&image);
+            if !self.aovs {
+                image.clear_aovs();
+            }
+        }
+        image.set_display(DisplayTransform::new(
+            self.exposure,
+            self.tone_mapper.clone(),
+        ));
+
+        image
+    }
+
+    /**
+     * Traces `min_samples` camera rays per pixel to their first hit, using
+     * the same random streams as the radiance so the AOVs line up with it.
+     */
+    fn render_aovs(
+        &self,
+        world: &dyn Hittable,
+        strata: i32,
+        control: &RenderControl,
+    ) -> Option<Aovs> {
+        let crop = self.crop_region();
+        let samples = self.min_samples().max(1);
+
+        let rows = (crop.y0 as i32..crop.y1 as i32)
             .into_par_iter()
             .map(|j| {
-                let remaining = self.image_height - j;
-                eprintln!("\rScanlines remaining: {remaining}");
-                let row_colors: Vec<Color> = (0..self.image_width)
-                    .into_par_iter()
-                    .map(|i| {
-                        let mut pixel_color: Color = Color::new(0., 0., 0.);
-                        for _sample in 0..self.samples_per_pixel {
-                            let r = self.get_ray(i, j);
-                            pixel_color = pixel_color + self.ray_color(r, self.max_depth, world);
-                        }
-                        pixel_color
-                    })
-                    .collect();
-                row_colors
+                if control.cancellation.is_cancelled() {
+                    return None;
+                }
+
+                let mut sampler = self.sampler.create(strata, self.seed);
+                let row = (crop.x0 as i32..crop.x1 as i32).map(|i| {
+                    let mut pixel = self.aov_sample(i, j, 0, world, &mut *sampler);
+                    for sample in 1..samples {
+                        let aov = self.aov_sample(i, j, sample, world, &mut *sampler);
+                        pixel.depth += aov.depth;
+                        pixel.normal += aov.normal;
+                        pixel.position += aov.position;
+                        pixel.uv = (pixel.uv.0 + aov.uv.0, pixel.uv.1 + aov.uv.1);
+                        pixel.albedo += aov.albedo;
+                    }
+
+                    let scale = 1. / samples as f64;
+                    pixel.depth *= scale;
+                    pixel.normal *= scale;
+                    pixel.position *= scale;
+                    pixel.uv = (pixel.uv.0 * scale, pixel.uv.1 * scale);
+                    pixel.albedo *= scale;
+                    pixel
+                });
+
+                Some(row.collect::<Vec<_>>())
             })
-            .collect();
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Aovs::new(
+            crop.x1 - crop.x0,
+            crop.y1 - crop.y0,
+            rows.concat(),
+        ))
+    }
+
+    fn aov_sample(
+        &self,
+        i: i32,
+        j: i32,
+        sample: i32,
+        world: &dyn Hittable,
+        sampler: &mut dyn Sampler,
+    ) -> AovSample {
+        seed_random(self.sample_seed(i, j, sample));
+        sampler.start_pixel_sample(i, j, sample);
+
+        let pixel_offset = sampler.get_2d();
+        let Some(r) = self.get_ray(i, j, pixel_offset, None, sampler) else {
+            return AovSample::default();
+        };
+
+        let mut rec = HitRecord::default();
+        stats::camera_ray();
+        if !world.hit(r, Interval::new(0.001, INFINITY), &mut rec) {
+            return AovSample {
+                albedo: self.background,
+                ..Default::default()
+            };
+        }
+
+        AovSample {
+            depth: match self.projection {
+                Projection::Perspective | Projection::Orthographic { .. } => {
+                    let view = self.view_at(r.time());
+                    (rec.p - view.center).dot(-view.w)
+                }
+                // Panoramic views have no single view axis to measure along.
+                _ => (rec.p - r.origin()).length(),
+            },
+            normal: rec.normal,
+            position: rec.p,
+            uv: (rec.u, rec.v),
+            albedo: rec.mat.albedo(&rec),
+            object_key: Some(rec.object_id),
+            material_key: Some(Arc::as_ptr(&rec.mat) as *const () as u64),
+        }
+    }
+
+    /**
+     * Checks that every setting is usable, so a render never runs into
+     * degenerate views or NaN rays.
+     */
+    pub(crate) fn validate(&self) -> Result<(), CameraError> {
+        if self.image_width < 1 || self.image_height < 1 {
+            return Err(CameraError::EmptyImage {
+                width: self.image_width,
+                height: self.image_height,
+            });
+        }
+        check(
+            "aspect_ratio",
+            self.aspect_ratio,
+            self.aspect_ratio > 0.,
+            "positive",
+        )?;
+        check(
+            "samples_per_pixel",
+            self.samples_per_pixel as f64,
+            self.samples_per_pixel >= 1,
+            "at least 1",
+        )?;
+        check(
+            "max_depth",
+            self.max_depth as f64,
+            self.max_depth >= 1,
+            "at least 1",
+        )?;
+        if let IntegratorType::AmbientOcclusion { radius } = self.integrator {
+            check("ambient occlusion radius", radius, radius > 0., "positive")?;
+        }
+        if let Some(min_depth) = self.russian_roulette {
+            check(
+                "russian_roulette",
+                min_depth as f64,
+                min_depth >= 0,
+                "zero or more",
+            )?;
+        }
+        if let Some(adaptive) = self.adaptive {
+            check(
+                "adaptive.max_samples",
+                adaptive.max_samples as f64,
+                adaptive.max_samples >= adaptive.min_samples,
+                "at least adaptive.min_samples",
+            )?;
+            check(
+                "adaptive.threshold",
+                adaptive.threshold,
+                adaptive.threshold >= 0.,
+                "zero or more",
+            )?;
+        }
 
-        for row in pixel_colors {
-            for pixel_color in row {
-                write_color(&mut stdout, pixel_color, self.samples_per_pixel);
+        match self.projection {
+            Projection::Orthographic { height } => {
+                check("orthographic height", height, height > 0., "positive")?
+            }
+            Projection::Fisheye { fov, .. } => check(
+                "fisheye fov",
+                fov,
+                fov > 0. && fov <= 360.,
+                "in (0, 360] degrees",
+            )?,
+            Projection::Perspective | Projection::Equirectangular => (),
+        }
+        if let Aperture::Polygon { blades, rotation } = self.lens.aperture {
+            check("aperture blades", blades as f64, blades >= 3, "at least 3")?;
+            check("aperture rotation", rotation, true, "finite")?;
+        }
+        check(
+            "lens.vignetting",
+            self.lens.vignetting,
+            self.lens.vignetting >= 0.,
+            "zero or more",
+        )?;
+        check(
+            "lens.distortion",
+            self.lens.distortion,
+            self.lens.distortion > -1.,
+            "greater than -1",
+        )?;
+        check(
+            "lens.chromatic_aberration",
+            self.lens.chromatic_aberration,
+            self.lens.chromatic_aberration.abs() < 1.,
+            "in (-1, 1)",
+        )?;
+        let radius = self.filter.radius();
+        check("filter radius", radius, radius > 0., "positive")?;
+        if let Some(crop) = self.crop {
+            let region = crop.region(self.image_width as usize, self.image_height as usize);
+            if region.is_empty() {
+                return Err(CameraError::EmptyCrop(crop));
             }
         }
+        check(
+            "focus_dist",
+            self.focus_dist,
+            self.focus_dist > 0.,
+            "positive",
+        )?;
+        check(
+            "defocus_angle",
+            self.defocus_angle,
+            (0. ..180.).contains(&self.defocus_angle),
+            "in [0, 180) degrees",
+        )?;
+        check("exposure", self.exposure, true, "finite")?;
+        check("shutter.open", self.shutter.open, true, "finite")?;
+        check(
+            "shutter.close",
+            self.shutter.close,
+            self.shutter.close >= self.shutter.open,
+            "at least shutter.open",
+        )?;
 
-        eprintln!("\rDone.                  \n")
+        self.validate_pose(self.lookfrom, self.lookat, self.vfov)?;
+        for keyframe in &self.keyframes {
+            check("keyframe time", keyframe.time, true, "finite")?;
+            self.validate_pose(keyframe.lookfrom, keyframe.lookat, keyframe.vfov)?;
+        }
+        if self
+            .keyframes
+            .windows(2)
+            .any(|pair| pair[0].time > pair[1].time)
+        {
+            return Err(CameraError::UnsortedKeyframes);
+        }
+
+        Ok(())
     }
 
-    fn initialize(&mut self) {
-        self.image_height = (self.image_width as f64 / self.aspect_ratio) as i32;
+    fn validate_pose(
+        &self,
+        lookfrom: Point3,
+        lookat: Point3,
+        vfov: f64,
+    ) -> Result<(), CameraError> {
+        for c in 0..3 {
+            check("lookfrom", lookfrom[c], true, "finite")?;
+            check("lookat", lookat[c], true, "finite")?;
+            check("vup", self.vup[c], true, "finite")?;
+        }
+        if self.projection == Projection::Perspective {
+            check(
+                "vfov",
+                vfov,
+                vfov > 0. && vfov < 180.,
+                "in (0, 180) degrees",
+            )?;
+        }
 
-        if self.image_height < 1 {
-            self.image_height = 1;
+        let w = lookfrom - lookat;
+        if w.length() <= 1e-12 * lookfrom.length().max(1.) {
+            return Err(CameraError::NoViewDirection);
+        }
+        if self.vup.cross(w).length() <= 1e-9 * self.vup.length() * w.length() {
+            return Err(CameraError::DegenerateUp);
         }
 
-        self.center = self.lookfrom;
+        Ok(())
+    }
+
+    /**
+     * Computes the view from the placement, once the settings are validated.
+     */
+    pub(crate) fn initialize(&mut self) {
+        self.view = self.view(self.lookfrom, self.lookat, self.vfov);
+    }
+
+    /**
+     * The pixels of the output: the crop window, or the whole image.
+     */
+    fn crop_region(&self) -> Tile {
+        let (width, height) = (self.image_width as usize, self.image_height as usize);
+        match &self.crop {
+            Some(crop) => crop.region(width, height),
+            None => Tile {
+                x0: 0,
+                y0: 0,
+                x1: width,
+                y1: height,
+            },
+        }
+    }
+
+    /**
+     * Splits the crop region into tiles. The region is widened by the pixels
+     * whose samples the filter spreads into it, so its border comes out the
+     * same as in the full render.
+     */
+    fn tiles(&self) -> Vec<Tile> {
+        let (width, height) = (self.image_width as usize, self.image_height as usize);
+        let crop = self.crop_region();
+        let margin = (self.filter.radius() - 0.5).max(0.).ceil() as usize;
+        let (x0, y0) = (
+            crop.x0.saturating_sub(margin),
+            crop.y0.saturating_sub(margin),
+        );
+        let (x1, y1) = (
+            (crop.x1 + margin).min(width),
+            (crop.y1 + margin).min(height),
+        );
+
+        generate_tiles(x1 - x0, y1 - y0, self.tile_size, self.tile_order)
+            .into_iter()
+            .map(|tile| Tile {
+                x0: tile.x0 + x0,
+                y0: tile.y0 + y0,
+                x1: tile.x1 + x0,
+                y1: tile.y1 + y0,
+            })
+            .collect()
+    }
+
+    fn view(&self, lookfrom: Point3, lookat: Point3, vfov: f64) -> View {
+        let center = lookfrom;
 
         // Camera
 
-        let theta = degrees_to_radians(self.vfov);
+        let theta = degrees_to_radians(vfov);
         let h = (theta / 2.).tan();
         let viewport_height = 2. * h * self.focus_dist;
         let viewport_width = viewport_height * (self.image_width as f64 / self.image_height as f64);
 
-        self.w = (self.lookfrom - self.lookat).unit_vector();
-        self.u = (self.vup.cross(self.w)).unit_vector();
-        self.v = self.w.cross(self.u);
+        let w = (lookfrom - lookat).unit_vector();
+        let u = (self.vup.cross(w)).unit_vector();
+        let v = w.cross(u);
 
-        let viewport_u = viewport_width * self.u;
-        let viewport_v = viewport_height * -self.v;
+        let viewport_u = viewport_width * u;
+        let viewport_v = viewport_height * -v;
 
-        self.pixel_delta_u = viewport_u / self.image_width as f64;
-        self.pixel_delta_v = viewport_v / self.image_height as f64;
+        let pixel_delta_u = viewport_u / self.image_width as f64;
+        let pixel_delta_v = viewport_v / self.image_height as f64;
 
         let viewport_upper_left =
-            self.center - (self.focus_dist * self.w) - viewport_u / 2. - viewport_v / 2.;
-        self.pixel00_loc = viewport_upper_left + 0.5 * (self.pixel_delta_u + self.pixel_delta_v);
+            center - (self.focus_dist * w) - viewport_u / 2. - viewport_v / 2.;
+        let pixel00_loc = viewport_upper_left + 0.5 * (pixel_delta_u + pixel_delta_v);
 
         let defocus_radius = self.focus_dist * degrees_to_radians(self.defocus_angle / 2.).tan();
-        self.defocus_disk_u = self.u * defocus_radius;
-        self.defocus_disk_v = self.v * defocus_radius;
+
+        View {
+            center,
+            pixel00_loc,
+            pixel_delta_u,
+            pixel_delta_v,
+            u,
+            v,
+            w,
+            defocus_disk_u: u * defocus_radius,
+            defocus_disk_v: v * defocus_radius,
+        }
     }
 
-    fn ray_color(&self, r: Ray, depth: i32, world: &dyn Hittable) -> Color {
-        let mut rec = HitRecord::default();
+    /**
+     * The view at scene time `time`, following the keyframes if there are any.
+     */
+    fn view_at(&self, time: f64) -> View {
+        if self.keyframes.is_empty() {
+            return self.view;
+        }
 
-        if depth <= 0 {
-            return Color::new(0., 0., 0.);
+        let keyframe = CameraKeyframe::interpolate(&self.keyframes, time);
+        self.view(keyframe.lookfrom, keyframe.lookat, keyframe.vfov)
+    }
+
+    fn min_samples(&self) -> i32 {
+        match self.adaptive {
+            Some(adaptive) => adaptive.min_samples,
+            None => self.samples_per_pixel,
         }
+    }
 
-        if !world.hit(r, Interval::new(0.001, INFINITY), &mut rec) {
-            return self.background;
+    fn max_samples(&self) -> i32 {
+        match self.adaptive {
+            Some(adaptive) => adaptive.max_samples,
+            None => self.samples_per_pixel,
+        }
+    }
+
+    /**
+     * Traces sample `sample` of pixel (i, j), splats it onto the tile and
+     * returns its radiance.
+     */
+    #[allow(clippy::too_many_arguments)]
+    fn sample_pixel(
+        &self,
+        i: i32,
+        j: i32,
+        sample: i32,
+        scene: &Scene,
+        integrator: &dyn Integrator,
+        sampler: &mut dyn Sampler,
+        tile: &mut FilmTile,
+    ) -> Color {
+        seed_random(self.sample_seed(i, j, sample));
+        sampler.start_pixel_sample(i, j, sample);
+
+        let pixel_offset = sampler.get_2d();
+
+        // With chromatic aberration every channel takes its own path.
+        let channels: &[Option<usize>] =
+            if self.lens.chromatic_aberration != 0. && self.projection == Projection::Perspective {
+                &[Some(0), Some(1), Some(2)]
+            } else {
+                &[None]
+            };
+
+        if self.heatmap.is_some() {
+            stats::start_trace();
+        }
+        let mut pixel_color = Color::default();
+        for &channel in channels {
+            if let Some(r) = self.get_ray(i, j, pixel_offset, channel, sampler) {
+                let color = integrator.radiance(r, scene, sampler);
+                match channel {
+                    Some(channel) => pixel_color[channel] = color[channel],
+                    None => pixel_color = color,
+                }
+            }
+        }
+        if let Some(heatmap) = self.heatmap {
+            let value = heatmap.value(&stats::trace());
+            pixel_color = Color::new(value, value, value);
+        }
+
+        tile.add_sample(
+            i as f64 + pixel_offset.0,
+            j as f64 + pixel_offset.1,
+            pixel_color,
+            &*self.filter,
+        );
+
+        pixel_color
+    }
+
+    /**
+     * Every pixel sample draws from its own random stream, so a render only
+     * depends on the seed and not on how the pixels are spread over threads.
+     */
+    fn sample_seed(&self, i: i32, j: i32, sample: i32) -> u64 {
+        let pixel = j as u64 * self.image_width as u64 + i as u64;
+        mix_seed(mix_seed(self.seed, pixel), sample as u64)
+    }
+
+    /**
+     * The camera ray through the sample at `pixel_offset` within pixel (i, j),
+     * as seen through the lens in the given color `channel`. Returns `None` if
+     * the projection does not cover that part of the image or the lens barrel
+     * blocks the ray.
+     */
+    fn get_ray(
+        &self,
+        i: i32,
+        j: i32,
+        pixel_offset: (f64, f64),
+        channel: Option<usize>,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Ray> {
+        // Always draw the lens and time samples so the dimensions used further
+        // down the path do not depend on the camera setup.
+        let lens_sample = sampler.get_2d();
+        let ray_time = self.shutter.sample(sampler.get_1d());
+        let view = self.view_at(ray_time);
+
+        let s = (i as f64 + pixel_offset.0) / self.image_width as f64;
+        let t = (j as f64 + pixel_offset.1) / self.image_height as f64;
+        let aspect_ratio = self.image_width as f64 / self.image_height as f64;
+
+        let (ray_origin, ray_direction) = match self.projection {
+            Projection::Perspective => {
+                // Film position from the center, with the corners at distance 1.
+                let diagonal = aspect_ratio.hypot(1.);
+                let film = (
+                    (2. * s - 1.) * aspect_ratio / diagonal,
+                    (1. - 2. * t) / diagonal,
+                );
+
+                let scale = self.lens.magnification(film, channel);
+                let x = (0.5 + (s - 0.5) * scale) * self.image_width as f64;
+                let y = (0.5 + (t - 0.5) * scale) * self.image_height as f64;
+                let pixel_sample = view.pixel00_loc
+                    + ((x - 0.5) * view.pixel_delta_u)
+                    + ((y - 0.5) * view.pixel_delta_v);
+
+                let ray_origin = if self.defocus_angle <= 0. {
+                    view.center
+                } else {
+                    let p = self.lens.sample_aperture(lens_sample);
+                    if self.lens.is_vignetted(p, film) {
+                        return None;
+                    }
+                    view.defocus_disk_sample(p)
+                };
+
+                (ray_origin, pixel_sample - ray_origin)
+            }
+            Projection::Orthographic { height } => {
+                let width = height * aspect_ratio;
+                let ray_origin =
+                    view.center + (s - 0.5) * width * view.u + (0.5 - t) * height * view.v;
+
+                (ray_origin, -view.w)
+            }
+            _ => {
+                let d = self.projection.direction(s, t, aspect_ratio)?;
+                (view.center, d[0] * view.u + d[1] * view.v + d[2] * view.w)
+            }
+        };
+
+        Some(Ray::new_with_time(ray_origin, ray_direction, ray_time))
+    }
+}
+
+fn check(
+    parameter: &'static str,
+    value: f64,
+    valid: bool,
+    expected: &'static str,
+) -> Result<(), CameraError> {
+    if valid && value.is_finite() {
+        Ok(())
+    } else {
+        Err(CameraError::OutOfRange {
+            parameter,
+            value,
+            expected,
+        })
+    }
+}
+
+/**
+ * Why a camera could not be built.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum CameraError {
+    /// The image has no pixels.
+    EmptyImage {
+        width: i32,
+        height: i32,
+    },
+    /// The image width and height do not give the requested aspect ratio.
+    InconsistentSize {
+        width: i32,
+        height: i32,
+        aspect_ratio: f64,
+    },
+    /// `lookfrom` and `lookat` are the same point, so there is no view
+    /// direction.
+    NoViewDirection,
+    /// `vup` is zero or parallel to the view direction, so there is no up.
+    DegenerateUp,
+    /// A setting is not a finite number in the range it is valid in.
+    OutOfRange {
+        parameter: &'static str,
+        value: f64,
+        expected: &'static str,
+    },
+    UnsortedKeyframes,
+    /// The crop window does not cover any pixels of the image.
+    EmptyCrop(CropWindow),
+}
+
+impl fmt::Display for CameraError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CameraError::EmptyImage { width, height } => {
+                write!(f, "the image size {}x{} has no pixels", width, height)
+            }
+            CameraError::InconsistentSize {
+                width,
+                height,
+                aspect_ratio,
+            } => write!(
+                f,
+                "the image size {}x{} does not have the aspect ratio {}",
+                width, height, aspect_ratio
+            ),
+            CameraError::NoViewDirection => {
+                write!(f, "lookfrom and lookat are the same point")
+            }
+            CameraError::DegenerateUp => {
+                write!(f, "vup is zero or parallel to the view direction")
+            }
+            CameraError::OutOfRange {
+                parameter,
+                value,
+                expected,
+            } => write!(f, "{} is {}, but must be {}", parameter, value, expected),
+            CameraError::UnsortedKeyframes => write!(f, "the keyframes are not sorted by time"),
+            CameraError::EmptyCrop(crop) => {
+                write!(f, "the crop window {:?} does not cover any pixels", crop)
+            }
         }
+    }
+}
 
-        let mut scattered = Ray::default();
-        let mut attenuation = Color::default();
-        let color_from_emission = rec.mat.emitted(rec.u, rec.v, rec.p);
+impl Error for CameraError {}
 
-        if !rec.mat.scatter(&r, &rec, &mut attenuation, &mut scattered) {
-            return color_from_emission;
+/**
+ * Collects the camera settings and checks them all at once in `build`. Unset
+ * settings default to a 100 pixel wide square image looking down -z from the
+ * origin with a 90 degree field of view.
+ */
+#[derive(Clone)]
+pub struct CameraBuilder {
+    camera: Camera,
+    aspect_ratio: Option<f64>,
+    image_height: Option<i32>,
+}
+
+impl Default for CameraBuilder {
+    fn default() -> Self {
+        Self {
+            camera: Camera {
+                aspect_ratio: 1.,
+                image_width: 100,
+                samples_per_pixel: 10,
+                max_depth: 10,
+                russian_roulette: None,
+
+                vfov: 90.,
+                projection: Projection::default(),
+                lookfrom: Point3::new(0., 0., 0.),
+                lookat: Point3::new(0., 0., -1.),
+                vup: Vec3::new(0., 1., 0.),
+
+                defocus_angle: 0.,
+                focus_dist: 10.,
+                lens: Lens::default(),
+                shutter: Shutter::default(),
+                keyframes: Vec::new(),
+
+                seed: 0,
+                sampler: SamplerType::default(),
+                filter: Arc::new(BoxFilter::default()),
+                adaptive: None,
+                tile_size: 32,
+                tile_order: TileOrder::default(),
+                crop: None,
+                aovs: false,
+                denoiser: None,
+                light_sampling: LightSampling::default(),
+                integrator: IntegratorType::default(),
+                heatmap: None,
+
+                exposure: 0.,
+                tone_mapper: Arc::new(Clamp),
+
+                image_height: 0,
+                view: View::default(),
+                background: Color::default(),
+            },
+            aspect_ratio: None,
+            image_height: None,
         }
+    }
+}
+
+impl CameraBuilder {
+    /**
+     * The ratio of image width over height. The height follows from it unless
+     * it is given too, in which case both have to agree.
+     */
+    pub fn aspect_ratio(mut self, aspect_ratio: f64) -> Self {
+        self.aspect_ratio = Some(aspect_ratio);
+        self
+    }
+
+    pub fn image_width(mut self, image_width: i32) -> Self {
+        self.camera.image_width = image_width;
+        self
+    }
+
+    pub fn image_height(mut self, image_height: i32) -> Self {
+        self.image_height = Some(image_height);
+        self
+    }
+
+    pub fn samples_per_pixel(mut self, samples_per_pixel: i32) -> Self {
+        self.camera.samples_per_pixel = samples_per_pixel;
+        self
+    }
+
+    pub fn max_depth(mut self, max_depth: i32) -> Self {
+        self.camera.max_depth = max_depth;
+        self
+    }
+
+    /**
+     * The number of bounces after which paths are ended by Russian roulette,
+     * or `None`, the default, to follow every path to `max_depth`.
+     */
+    pub fn russian_roulette(mut self, min_depth: Option<i32>) -> Self {
+        self.camera.russian_roulette = min_depth;
+        self
+    }
+
+    /**
+     * The vertical field of view in degrees.
+     */
+    pub fn vfov(mut self, vfov: f64) -> Self {
+        self.camera.vfov = vfov;
+        self
+    }
+
+    pub fn projection(mut self, projection: Projection) -> Self {
+        self.camera.projection = projection;
+        self
+    }
+
+    pub fn lookfrom(mut self, lookfrom: Point3) -> Self {
+        self.camera.lookfrom = lookfrom;
+        self
+    }
+
+    pub fn lookat(mut self, lookat: Point3) -> Self {
+        self.camera.lookat = lookat;
+        self
+    }
+
+    pub fn vup(mut self, vup: Vec3) -> Self {
+        self.camera.vup = vup;
+        self
+    }
+
+    pub fn defocus_angle(mut self, defocus_angle: f64) -> Self {
+        self.camera.defocus_angle = defocus_angle;
+        self
+    }
+
+    pub fn focus_dist(mut self, focus_dist: f64) -> Self {
+        self.camera.focus_dist = focus_dist;
+        self
+    }
+
+    /**
+     * Sets the aperture shape and lens imperfections. With chromatic
+     * aberration every camera sample traces three rays, one per color
+     * channel, so it takes about three times as long to render.
+     */
+    pub fn lens(mut self, lens: Lens) -> Self {
+        self.camera.lens = lens;
+        self
+    }
+
+    pub fn shutter(mut self, shutter: Shutter) -> Self {
+        self.camera.shutter = shutter;
+        self
+    }
+
+    /**
+     * Camera placements over the shutter interval, sorted by time. They
+     * replace `lookfrom`, `lookat` and `vfov` while rendering.
+     */
+    pub fn keyframes(mut self, keyframes: Vec<CameraKeyframe>) -> Self {
+        self.camera.keyframes = keyframes;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.camera.seed = seed;
+        self
+    }
+
+    pub fn sampler(mut self, sampler: SamplerType) -> Self {
+        self.camera.sampler = sampler;
+        self
+    }
+
+    pub fn filter(mut self, filter: Arc<dyn Filter>) -> Self {
+        self.camera.filter = filter;
+        self
+    }
+
+    pub fn adaptive(mut self, adaptive: AdaptiveSampling) -> Self {
+        self.camera.adaptive = Some(adaptive);
+        self
+    }
+
+    pub fn tile_size(mut self, tile_size: usize) -> Self {
+        self.camera.tile_size = tile_size;
+        self
+    }
+
+    pub fn tile_order(mut self, tile_order: TileOrder) -> Self {
+        self.camera.tile_order = tile_order;
+        self
+    }
 
-        let color_from_scatter = attenuation * self.ray_color(scattered, depth - 1, world);
+    /**
+     * Renders only this part of the image, with the same rays as in the full
+     * frame.
+     */
+    pub fn crop(mut self, crop: CropWindow) -> Self {
+        self.camera.crop = Some(crop);
+        self
+    }
+
+    pub fn aovs(mut self, aovs: bool) -> Self {
+        self.camera.aovs = aovs;
+        self
+    }
 
-        color_from_emission + color_from_scatter
+    pub fn denoiser(mut self, denoiser: Denoiser) -> Self {
+        self.camera.denoiser = Some(denoiser);
+        self
     }
 
-    fn get_ray(self, i: i32, j: i32) -> Ray {
-        let pixel_center =
-            self.pixel00_loc + (i as f64 * self.pixel_delta_u) + (j as f64 * self.pixel_delta_v);
-        let pixel_sample = pixel_center + self.pixel_sample_square();
+    /**
+     * How the light at diffuse and glossy hits is found, to compare the
+     * strategies. Defaults to combining both with the power heuristic.
+     */
+    pub fn light_sampling(mut self, light_sampling: LightSampling) -> Self {
+        self.camera.light_sampling = light_sampling;
+        self
+    }
+
+    /**
+     * The algorithm turning camera rays into light, the path tracer by
+     * default.
+     */
+    pub fn integrator(mut self, integrator: IntegratorType) -> Self {
+        self.camera.integrator = integrator;
+        self
+    }
 
-        let ray_origin = if self.defocus_angle <= 0. {
-            self.center
-        } else {
-            self.defocus_disk_sample()
+    /**
+     * Renders a false color heatmap of the work done per pixel instead of
+     * radiance. The denoiser and the display transform are not applied, and
+     * a box filter of radius 0.5 replaces the pixel filter so every sample
+     * only counts towards its own pixel.
+     */
+    pub fn heatmap(mut self, heatmap: Heatmap) -> Self {
+        self.camera.heatmap = Some(heatmap);
+        self
+    }
+
+    /**
+     * The exposure adjustment in stops applied before tone mapping.
+     */
+    pub fn exposure(mut self, exposure: f64) -> Self {
+        self.camera.exposure = exposure;
+        self
+    }
+
+    pub fn tone_mapper(mut self, tone_mapper: Arc<dyn ToneMapper>) -> Self {
+        self.camera.tone_mapper = tone_mapper;
+        self
+    }
+
+    pub fn background(mut self, background: Color) -> Self {
+        self.camera.background = background;
+        self
+    }
+
+    pub fn build(self) -> Result<Camera, CameraError> {
+        let mut camera = self.camera;
+        let width = camera.image_width;
+
+        match (self.aspect_ratio, self.image_height) {
+            (Some(aspect_ratio), Some(height)) => {
+                camera.aspect_ratio = aspect_ratio;
+                camera.image_height = height;
+                if (width as f64 / aspect_ratio) as i32 != height {
+                    return Err(CameraError::InconsistentSize {
+                        width,
+                        height,
+                        aspect_ratio,
+                    });
+                }
+            }
+            (Some(aspect_ratio), None) => {
+                check("aspect_ratio", aspect_ratio, aspect_ratio > 0., "positive")?;
+                camera.aspect_ratio = aspect_ratio;
+                camera.image_height = ((width as f64 / aspect_ratio) as i32).max(1);
+            }
+            (None, Some(height)) => {
+                camera.aspect_ratio = width as f64 / height as f64;
+                camera.image_height = height;
+            }
+            (None, None) => camera.image_height = width,
+        }
+
+        if camera.heatmap.is_some() {
+            camera.filter = Arc::new(BoxFilter::default());
+        }
+
+        camera.validate()?;
+        camera.initialize();
+
+        Ok(camera)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::{GaussianFilter, LanczosFilter, MitchellFilter, TentFilter};
+    use crate::hittable_list::HittableList;
+    use crate::material::{Dielectric, Lambertian, Metal};
+    use crate::mis::Heuristic;
+    use crate::scheduler::CancellationToken;
+    use crate::sphere::Sphere;
+    use crate::test_scenes::{lit_floor, mean};
+
+    #[test]
+    fn test_render_returns_background() {
+        let builder = Camera::builder()
+            .aspect_ratio(2.)
+            .image_width(8)
+            .samples_per_pixel(4)
+            .max_depth(5)
+            .vfov(90.)
+            .lookfrom(Point3::new(0., 0., 1.))
+            .lookat(Point3::new(0., 0., 0.))
+            .vup(Vec3::new(0., 1., 0.))
+            .background(Color::new(0.25, 0.5, 0.75));
+
+        let filters: [Arc<dyn Filter>; 5] = [
+            Arc::new(BoxFilter::default()),
+            Arc::new(TentFilter::default()),
+            Arc::new(GaussianFilter::default()),
+            Arc::new(MitchellFilter::default()),
+            Arc::new(LanczosFilter::default()),
+        ];
+
+        for filter in filters {
+            let camera = builder.clone().filter(filter).build().unwrap();
+            let image = camera.clone().render(&HittableList::default());
+
+            assert_eq!(image.width(), 8);
+            assert_eq!(image.height(), 4);
+            for pixel in image.pixels() {
+                assert!((*pixel - camera.background).near_zero());
+            }
+        }
+    }
+
+    #[test]
+    fn test_builder_validation() {
+        let camera = Camera::builder()
+            .image_width(32)
+            .aspect_ratio(2.)
+            .build()
+            .unwrap();
+        assert_eq!((camera.image_width(), camera.image_height()), (32, 16));
+        let camera = Camera::builder()
+            .image_width(30)
+            .image_height(20)
+            .build()
+            .unwrap();
+        assert_eq!(camera.aspect_ratio, 1.5);
+
+        let error = |builder: CameraBuilder| builder.build().err().unwrap();
+        assert_eq!(
+            error(Camera::builder().image_width(0)),
+            CameraError::EmptyImage {
+                width: 0,
+                height: 0
+            }
+        );
+        assert_eq!(
+            error(
+                Camera::builder()
+                    .image_width(30)
+                    .image_height(20)
+                    .aspect_ratio(2.)
+            ),
+            CameraError::InconsistentSize {
+                width: 30,
+                height: 20,
+                aspect_ratio: 2.
+            }
+        );
+        assert_eq!(
+            error(Camera::builder().lookat(Point3::new(0., 0., 0.))),
+            CameraError::NoViewDirection
+        );
+        assert_eq!(
+            error(Camera::builder().vup(Vec3::new(0., 0., 2.))),
+            CameraError::DegenerateUp
+        );
+        assert!(matches!(
+            error(Camera::builder().vfov(0.)),
+            CameraError::OutOfRange {
+                parameter: "vfov",
+                ..
+            }
+        ));
+        assert!(matches!(
+            error(Camera::builder().focus_dist(f64::NAN)),
+            CameraError::OutOfRange {
+                parameter: "focus_dist",
+                ..
+            }
+        ));
+        assert_eq!(
+            error(Camera::builder().keyframes(vec![
+                CameraKeyframe::new(1., Point3::new(0., 0., 1.), Point3::default(), 40.),
+                CameraKeyframe::new(0., Point3::new(0., 0., 1.), Point3::default(), 40.),
+            ])),
+            CameraError::UnsortedKeyframes
+        );
+        let outside = CropWindow::Pixels {
+            x0: 100,
+            y0: 0,
+            x1: 200,
+            y1: 100,
+        };
+        assert_eq!(
+            error(Camera::builder().crop(outside)),
+            CameraError::EmptyCrop(outside)
+        );
+        assert!(matches!(
+            error(Camera::builder().lens(Lens {
+                chromatic_aberration: 1.,
+                ..Lens::default()
+            })),
+            CameraError::OutOfRange {
+                parameter: "lens.chromatic_aberration",
+                ..
+            }
+        ));
+        assert!(matches!(
+            error(Camera::builder().filter(Arc::new(BoxFilter::new(0.)))),
+            CameraError::OutOfRange {
+                parameter: "filter radius",
+                ..
+            }
+        ));
+
+        // Orthographic cameras do not use the field of view.
+        assert!(Camera::builder()
+            .vfov(0.)
+            .projection(Projection::Orthographic { height: 2. })
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_render_is_reproducible() {
+        let mut world = HittableList::default();
+        world.add(Box::new(Sphere::new(
+            Point3::new(0., -100.5, -1.),
+            100.,
+            Arc::new(Lambertian::new_from_color(Color::new(0.8, 0.8, 0.))),
+        )));
+        world.add(Box::new(Sphere::new(
+            Point3::new(-0.5, 0., -1.),
+            0.5,
+            Arc::new(Dielectric::new(1.5)),
+        )));
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.5, 0., -1.),
+            0.5,
+            Arc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.3)),
+        )));
+
+        let builder = Camera::builder()
+            .aspect_ratio(16. / 9.)
+            .image_width(32)
+            .samples_per_pixel(8)
+            .max_depth(10)
+            .vfov(90.)
+            .lookfrom(Point3::new(0., 0., 1.))
+            .lookat(Point3::new(0., 0., -1.))
+            .vup(Vec3::new(0., 1., 0.))
+            .defocus_angle(2.)
+            .background(Color::new(0.7, 0.8, 1.))
+            .filter(Arc::new(MitchellFilter::default()));
+        let camera = builder.clone().seed(42).build().unwrap();
+
+        let render_with_threads = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| camera.clone().render(&world))
         };
 
-        let ray_direction = pixel_sample - ray_origin;
-        let ray_time = random_double();
+        let first = render_with_threads(1);
+        let second = render_with_threads(4);
+        assert!(first.pixels() == second.pixels());
 
-        Ray::new_with_time(ray_origin, ray_direction, ray_time)
+        let reseeded = builder.seed(7).build().unwrap().render(&world);
+        assert!(first.pixels() != reseeded.pixels());
     }
 
-    fn defocus_disk_sample(self) -> Point3 {
-        let p = Vec3::random_in_unit_disk();
-        self.center + (p[0] * self.defocus_disk_u) + (p[1] * self.defocus_disk_v)
+    #[test]
+    fn test_crop_window_matches_full_render() {
+        let mut world = HittableList::default();
+        world.add(Box::new(Sphere::new(
+            Point3::new(0., -100.5, -1.),
+            100.,
+            Arc::new(Lambertian::new_from_color(Color::new(0.8, 0.8, 0.))),
+        )));
+        world.add(Box::new(Sphere::new(
+            Point3::new(0., 0., -1.),
+            0.5,
+            Arc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.3)),
+        )));
+
+        let builder = Camera::builder()
+            .aspect_ratio(2.)
+            .image_width(32)
+            .samples_per_pixel(4)
+            .max_depth(10)
+            .vfov(90.)
+            .lookfrom(Point3::new(0., 0., 1.))
+            .lookat(Point3::new(0., 0., -1.))
+            .vup(Vec3::new(0., 1., 0.))
+            .background(Color::new(0.7, 0.8, 1.))
+            .filter(Arc::new(MitchellFilter::default()))
+            .tile_size(8);
+        let full = builder
+            .clone()
+            .build()
+            .unwrap()
+            .render_with(&world, &RenderControl::new());
+
+        let camera = builder
+            .crop(CropWindow::Normalized {
+                x0: 0.3,
+                y0: 0.25,
+                x1: 0.7,
+                y1: 0.75,
+            })
+            .build()
+            .unwrap();
+        let crop = camera.clone().render_with(&world, &RenderControl::new());
+        assert_eq!((crop.width(), crop.height()), (14, 8));
+        for y in 0..crop.height() {
+            for x in 0..crop.width() {
+                let difference = crop.pixel(x, y) - full.pixel(x + 9, y + 4);
+                assert!(difference.length() < 1e-9);
+            }
+        }
+
+        let mut composite = Framebuffer::new(32, 16);
+        camera.render_into(&world, &mut composite, &RenderControl::new());
+        assert!(composite.pixel(9, 4) == crop.pixel(0, 0));
+        assert!(composite.pixel(8, 4) == Color::default());
+    }
+
+    #[test]
+    fn test_adaptive_sampling() {
+        let mut world = HittableList::default();
+        world.add(Box::new(Sphere::new(
+            Point3::new(0., -100.5, -1.),
+            100.,
+            Arc::new(Lambertian::new_from_color(Color::new(0.5, 0.5, 0.5))),
+        )));
+        world.add(Box::new(Sphere::new(
+            Point3::new(0., 0., -1.),
+            0.5,
+            Arc::new(Lambertian::new_from_color(Color::new(0.5, 0.5, 0.5))),
+        )));
+
+        let camera = Camera::builder()
+            .aspect_ratio(1.)
+            .image_width(16)
+            .samples_per_pixel(1)
+            .max_depth(10)
+            .vfov(90.)
+            .lookfrom(Point3::new(0., 0., 0.))
+            .lookat(Point3::new(0., 0., -1.))
+            .vup(Vec3::new(0., 1., 0.))
+            .background(Color::new(0.7, 0.8, 1.))
+            .adaptive(AdaptiveSampling::new(8, 64, 0.01))
+            .build()
+            .unwrap();
+
+        let image = camera.render(&world);
+        let counts = image.sample_counts().unwrap();
+
+        // The flat background converges right away, the lit sphere does not.
+        assert_eq!(counts[0], 8);
+        assert_eq!(counts[8 * 16 + 8], 64);
+        assert!(counts.iter().all(|&count| (8..=64).contains(&count)));
+    }
+
+    #[test]
+    fn test_progress_and_cancellation() {
+        let camera = Camera::builder()
+            .aspect_ratio(2.)
+            .image_width(64)
+            .samples_per_pixel(4)
+            .max_depth(5)
+            .vfov(90.)
+            .lookfrom(Point3::new(0., 0., 1.))
+            .lookat(Point3::new(0., 0., 0.))
+            .vup(Vec3::new(0., 1., 0.))
+            .background(Color::new(0.25, 0.5, 0.75))
+            .tile_size(8)
+            .tile_order(TileOrder::Spiral)
+            .build()
+            .unwrap();
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let recorded = reports.clone();
+        let control = RenderControl::new().on_progress(move |progress| {
+            recorded.lock().unwrap().push(progress.completed_tiles);
+        });
+        camera
+            .clone()
+            .render_with(&HittableList::default(), &control);
+        assert_eq!(*reports.lock().unwrap(), (1..=32).collect::<Vec<_>>());
+
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        let control = RenderControl::new()
+            .with_cancellation(token)
+            .on_progress(move |_| canceller.cancel());
+        let image = camera
+            .clone()
+            .render_with(&HittableList::default(), &control);
+
+        let rendered = image
+            .pixels()
+            .iter()
+            .filter(|&&pixel| (pixel - camera.background).near_zero())
+            .count();
+        assert!(rendered >= 64);
+        assert!(rendered < image.pixels().len());
     }
 
-    fn pixel_sample_square(self) -> Vec3 {
-        let px = -0.5 + random_double();
-        let py = -0.5 + random_double();
-        (px * self.pixel_delta_u) + (py * self.pixel_delta_v)
+    #[test]
+    fn test_progressive_resume() {
+        let mut world = HittableList::default();
+        let ground = Lambertian::new_from_color(Color::new(0.5, 0.5, 0.5));
+        world.add(Box::new(Sphere::new(
+            Point3::new(0., -100.5, -1.),
+            100.,
+            Arc::new(ground),
+        )));
+
+        let builder = Camera::builder()
+            .aspect_ratio(2.)
+            .image_width(16)
+            .samples_per_pixel(8)
+            .max_depth(5)
+            .vfov(90.)
+            .lookfrom(Point3::new(0., 0., 1.))
+            .lookat(Point3::new(0., 0., 0.))
+            .vup(Vec3::new(0., 1., 0.))
+            .background(Color::new(0.7, 0.8, 1.))
+            .seed(3);
+        let camera = builder.clone().build().unwrap();
+
+        let path = std::env::temp_dir().join(format!("resume-{}.checkpoint", std::process::id()));
+        let settings = ProgressiveSettings {
+            pass_samples: 3,
+            checkpoint: Some(path.clone()),
+            resume: true,
+            ..Default::default()
+        };
+        let control = RenderControl::new();
+
+        // Render half the samples, then resume with the full budget.
+        let first = builder.clone().samples_per_pixel(4).build().unwrap();
+        first
+            .render_progressive(&world, &settings, &control)
+            .unwrap();
+        assert_eq!(
+            Checkpoint::load(&path).unwrap().sample_counts(),
+            vec![4; 128]
+        );
+        let resumed = camera
+            .clone()
+            .render_progressive(&world, &settings, &control)
+            .unwrap();
+        assert_eq!(
+            Checkpoint::load(&path).unwrap().sample_counts(),
+            vec![8; 128]
+        );
+        // The sampler keeps the strata of the budget the render started with.
+        assert_eq!(Checkpoint::load(&path).unwrap().strata(), 4);
+
+        let direct = camera.clone().render_with(&world, &control);
+        for (a, b) in resumed.pixels().iter().zip(direct.pixels()) {
+            assert!((*a - *b).length() < 1e-9);
+        }
+
+        let moved = builder.lookfrom(Point3::new(0., 0., 2.)).build().unwrap();
+        assert!(moved
+            .render_progressive(&world, &settings, &control)
+            .is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_first_hit_aovs() {
+        let mut world = HittableList::default();
+        let ground = Arc::new(Lambertian::new_from_color(Color::new(0.5, 0.5, 0.5)));
+        let red = Arc::new(Lambertian::new_from_color(Color::new(0.8, 0.1, 0.1)));
+        world.add(Box::new(Sphere::new(
+            Point3::new(0., -100.5, -1.),
+            100.,
+            ground,
+        )));
+        world.add(Box::new(Sphere::new(Point3::new(0., 0., -1.), 0.5, red)));
+
+        let camera = Camera::builder()
+            .aspect_ratio(2.)
+            .image_width(64)
+            .samples_per_pixel(4)
+            .max_depth(5)
+            .vfov(90.)
+            .lookfrom(Point3::new(0., 0., 1.))
+            .lookat(Point3::new(0., 0., 0.))
+            .vup(Vec3::new(0., 1., 0.))
+            .background(Color::new(0.7, 0.8, 1.))
+            .aovs(true)
+            .build()
+            .unwrap();
+
+        let image = camera.clone().render_with(&world, &RenderControl::new());
+        let aovs = image.aovs().expect("missing AOVs");
+        let center = 16 * 64 + 32;
+
+        assert!((aovs.depth()[center] - 1.5).abs() < 0.05);
+        assert!(aovs.normal()[center][2] > 0.95);
+        assert!(aovs.albedo()[center] == Color::new(0.8, 0.1, 0.1));
+        assert!(aovs.albedo()[0] == camera.background);
+        assert_eq!(aovs.object_id()[0], 0);
+        assert_eq!(aovs.material_id()[0], 0);
+
+        let bottom = 31 * 64 + 32;
+        assert!(aovs.object_id()[center] > 0 && aovs.object_id()[bottom] > 0);
+        assert_ne!(aovs.object_id()[center], aovs.object_id()[bottom]);
+        assert_ne!(aovs.material_id()[center], aovs.material_id()[bottom]);
+    }
+
+    #[test]
+    fn test_heatmaps() {
+        let mut world = HittableList::default();
+        let red = Arc::new(Lambertian::new_from_color(Color::new(0.8, 0.1, 0.1)));
+        world.add(Box::new(Sphere::new(Point3::new(0., 0., -1.), 0.5, red)));
+
+        let builder = || {
+            Camera::builder()
+                .aspect_ratio(2.)
+                .image_width(32)
+                .samples_per_pixel(4)
+                .max_depth(5)
+                .lookfrom(Point3::new(0., 0., 1.))
+                .lookat(Point3::new(0., 0., 0.))
+                .filter(Arc::new(MitchellFilter::default()))
+        };
+        let center = 8 * 32 + 16;
+
+        // Rays that miss the sphere end without a bounce. Samples are not
+        // spread into the neighbouring pixels.
+        let camera = builder().heatmap(Heatmap::PathLength).build().unwrap();
+        assert_eq!(camera.filter.radius(), 0.5);
+        let path_length = camera.render_with(&world, &RenderControl::new());
+        assert!(path_length.pixel(0, 0) == Color::new(0., 0., 0.));
+        assert!(path_length.pixels()[center] != Color::new(0., 0., 0.));
+        assert!(path_length.heatmap_scale().unwrap() >= 1.);
+
+        // Every camera ray is tested against the sphere.
+        let tests = builder()
+            .heatmap(Heatmap::IntersectionTests)
+            .build()
+            .unwrap()
+            .render_with(&world, &RenderControl::new());
+        assert!(tests.pixels().iter().all(|&p| p != Color::new(0., 0., 0.)));
+    }
+
+    /**
+     * The mean of a tiny render around the origin.
+     */
+    fn render_floor(
+        world: &HittableList,
+        lookfrom: Point3,
+        samples_per_pixel: i32,
+        light_sampling: LightSampling,
+    ) -> f64 {
+        let image = Camera::builder()
+            .image_width(2)
+            .samples_per_pixel(samples_per_pixel)
+            .max_depth(4)
+            .vfov(0.1)
+            .lookfrom(lookfrom)
+            .lookat(Point3::new(0., 0., 0.))
+            .light_sampling(light_sampling)
+            .build()
+            .unwrap()
+            .render_with(world, &RenderControl::new());
+
+        mean(&image)[0]
+    }
+
+    #[test]
+    fn test_direct_light_matches_irradiance() {
+        let white = Arc::new(Lambertian::new_from_color(Color::new(0.5, 0.5, 0.5)));
+
+        // The radiance of the floor below the center of the light, from the
+        // irradiance integrated over the light.
+        let expected = |half_size: f64| {
+            let n = 200;
+            let cell = 2. * half_size / n as f64;
+            let irradiance: f64 = (0..n * n)
+                .map(|k| {
+                    let x = -half_size + cell * ((k % n) as f64 + 0.5);
+                    let z = -half_size + cell * ((k / n) as f64 + 0.5);
+                    cell * cell / (x * x + z * z + 1.).powi(2)
+                })
+                .sum::<f64>()
+                * 4.;
+            0.5 / crate::PI * irradiance
+        };
+
+        let lookfrom = Point3::new(0., 0.5, 3.);
+        let small = lit_floor(white.clone(), 0.25);
+        for sampling in [LightSampling::Lights, LightSampling::Mis(Heuristic::Power)] {
+            let value = render_floor(&small, lookfrom, 256, sampling);
+            assert!((value - expected(0.25)).abs() < 0.02 * expected(0.25));
+        }
+
+        // All strategies converge to the same light, where hitting a large
+        // one by chance is likely enough to compare.
+        let large = lit_floor(white, 1.);
+        for sampling in [
+            LightSampling::Bsdf,
+            LightSampling::Lights,
+            LightSampling::Mis(Heuristic::Balance),
+            LightSampling::Mis(Heuristic::Power),
+        ] {
+            let value = render_floor(&large, lookfrom, 2048, sampling);
+            assert!((value - expected(1.)).abs() < 0.05 * expected(1.));
+        }
+    }
+
+    #[test]
+    fn test_light_sampling_strategies_agree_on_glossy_metal() {
+        let metal = Arc::new(Metal::new(Color::new(0.8, 0.8, 0.8), 0.5));
+        let world = lit_floor(metal, 0.75);
+        let lookfrom = Point3::new(0., 2.5, 3.);
+
+        let reference = render_floor(&world, lookfrom, 4096, LightSampling::Bsdf);
+        assert!(reference > 0.1);
+        for sampling in [
+            LightSampling::Lights,
+            LightSampling::Mis(Heuristic::Balance),
+            LightSampling::Mis(Heuristic::Power),
+        ] {
+            let value = render_floor(&world, lookfrom, 1024, sampling);
+            assert!((value - reference).abs() < 0.05 * reference);
+        }
+    }
+
+    #[test]
+    fn test_russian_roulette_is_unbiased() {
+        let mut world = HittableList::default();
+        let white = Arc::new(Lambertian::new_from_color(Color::new(0.8, 0.8, 0.8)));
+        world.add(Box::new(Sphere::new(
+            Point3::new(0., -100.5, -1.),
+            100.,
+            white.clone(),
+        )));
+        world.add(Box::new(Sphere::new(
+            Point3::new(-0.5, 0., -1.),
+            0.5,
+            white,
+        )));
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.5, 0., -1.),
+            0.5,
+            Arc::new(Dielectric::new(1.5)),
+        )));
+
+        let render = |russian_roulette: Option<i32>| {
+            let image = Camera::builder()
+                .aspect_ratio(2.)
+                .image_width(16)
+                .samples_per_pixel(256)
+                .max_depth(50)
+                .russian_roulette(russian_roulette)
+                .lookfrom(Point3::new(0., 0., 1.))
+                .lookat(Point3::new(0., 0., -1.))
+                .background(Color::new(1., 1., 1.))
+                .build()
+                .unwrap()
+                .render_with(&world, &RenderControl::new());
+            mean(&image)[0]
+        };
+
+        let full = render(None);
+        let roulette = render(Some(0));
+        assert!((roulette - full).abs() < 0.01 * full);
     }
 }
@@ -1,41 +1,58 @@
 use crate::interval::Interval;
 use crate::vec3::Vec3;
-use std::io::Write;
+use std::io::{self, Write};
 
 pub type Color = Vec3;
 
-fn linear_to_gamma(linear_component: f64) -> f64 {
-    linear_component.sqrt()
+/**
+ * The exact sRGB transfer function (OETF) for a linear component in [0, 1].
+ */
+pub fn linear_to_srgb(linear_component: f64) -> f64 {
+    if linear_component <= 0.0031308 {
+        12.92 * linear_component
+    } else {
+        1.055 * linear_component.powf(1. / 2.4) - 0.055
+    }
 }
 
-pub fn write_color(out: &mut dyn Write, pixel_color: Color, samples_per_pixel: i32) {
-    let mut r = pixel_color.x();
-    let mut g = pixel_color.y();
-    let mut b = pixel_color.z();
+/**
+ * The inverse of `linear_to_srgb`.
+ */
+pub fn srgb_to_linear(display_component: f64) -> f64 {
+    if display_component <= 0.04045 {
+        display_component / 12.92
+    } else {
+        ((display_component + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/**
+ * Relative luminance of a linear Rec. 709 color.
+ */
+pub fn luminance(c: Color) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
 
-    // Divide the color by the number of samples.
-    let scale = 1.0 / samples_per_pixel as f64;
-    r *= scale;
-    g *= scale;
-    b *= scale;
+/**
+ * Quantizes a display-encoded color component to `levels` steps.
+ */
+fn quantize(display_component: f64, levels: f64) -> f64 {
+    // Clamp the color component to the valid range [0.0, 1.0).
+    let intensity = Interval::new(0.000, (levels - 1.) / levels);
+    (levels * intensity.clamp(display_component)).floor()
+}
 
-    r = linear_to_gamma(r);
-    g = linear_to_gamma(g);
-    b = linear_to_gamma(b);
+pub fn to_rgb8(display_color: Color) -> [u8; 3] {
+    [0, 1, 2].map(|i| quantize(display_color[i], 256.) as u8)
+}
+
+pub fn to_rgb16(display_color: Color) -> [u16; 3] {
+    [0, 1, 2].map(|i| quantize(display_color[i], 65536.) as u16)
+}
 
-    // Clamp the color components to the valid range [0.0, 0.999].
-    let intensity = Interval::new(0.000, 0.999);
-    r = intensity.clamp(r);
-    g = intensity.clamp(g);
-    b = intensity.clamp(b);
+pub fn write_color(out: &mut dyn Write, display_color: Color) -> io::Result<()> {
+    let [r, g, b] = to_rgb8(display_color);
 
     // Write the translated [0,255] value of each color component.
-    writeln!(
-        out,
-        "{} {} {}",
-        (256.0 * r) as i32,
-        (256.0 * g) as i32,
-        (256.0 * b) as i32
-    )
-    .expect("TODO: panic message");
+    writeln!(out, "{} {} {}", r, g, b)
 }
@@ -0,0 +1,207 @@
+use crate::aov::Aovs;
+use crate::color::Color;
+use crate::framebuffer::Framebuffer;
+use crate::vec3::Vec3;
+use rayon::prelude::*;
+
+/// The B3 spline the à-trous wavelet is built from.
+const KERNEL: [f64; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+/**
+ * An edge-avoiding à-trous wavelet filter (Dammertz et al. 2010). Every
+ * iteration blurs with a 5x5 kernel whose taps are twice as far apart as in
+ * the previous one, and stops at edges by weighting each tap by how similar
+ * its color, normal, albedo and depth are to the center pixel.
+ *
+ * With AOVs the radiance is divided by the albedo first, so textures are kept
+ * sharp and only the lighting is smoothed. Without them only the color is
+ * used to find edges.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Denoiser {
+    pub iterations: u32,
+    pub color_sigma: f64,
+    pub normal_sigma: f64,
+    pub albedo_sigma: f64,
+    pub depth_sigma: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            color_sigma: 0.6,
+            normal_sigma: 0.1,
+            albedo_sigma: 0.1,
+            depth_sigma: 0.1,
+        }
+    }
+}
+
+impl Denoiser {
+    pub fn denoise(&self, image: &Framebuffer) -> Framebuffer {
+        let aovs = image.aovs();
+        let albedo = |k: usize| match aovs {
+            Some(aovs) => aovs.albedo()[k],
+            None => Color::new(1., 1., 1.),
+        };
+        // Black albedo has no lighting to recover, so leave those channels as is.
+        let demodulate = |c: f64, a: f64| if a > 1e-3 { c / a } else { c };
+        let remodulate = |c: f64, a: f64| if a > 1e-3 { c * a } else { c };
+
+        let mut color: Vec<Color> = image
+            .pixels()
+            .iter()
+            .enumerate()
+            .map(|(k, &c)| {
+                let a = albedo(k);
+                Color::new(
+                    demodulate(c[0], a[0]),
+                    demodulate(c[1], a[1]),
+                    demodulate(c[2], a[2]),
+                )
+            })
+            .collect();
+
+        for iteration in 0..self.iterations {
+            color = self.filter(image.width(), image.height(), &color, aovs, iteration);
+        }
+
+        let mut denoised = image.clone();
+        for (k, pixel) in denoised.pixels_mut().iter_mut().enumerate() {
+            let a = albedo(k);
+            let c = color[k];
+            *pixel = Color::new(
+                remodulate(c[0], a[0]),
+                remodulate(c[1], a[1]),
+                remodulate(c[2], a[2]),
+            );
+        }
+
+        denoised
+    }
+
+    fn filter(
+        &self,
+        width: usize,
+        height: usize,
+        color: &[Color],
+        aovs: Option<&Aovs>,
+        iteration: u32,
+    ) -> Vec<Color> {
+        let step = 1 << iteration;
+        // Finer details are already gone after the first iterations, so the
+        // color weight gets stricter to not blur across the remaining edges.
+        let color_sigma = self.color_sigma / 2f64.powi(iteration as i32);
+
+        (0..height)
+            .into_par_iter()
+            .flat_map_iter(|y| {
+                (0..width).map(move |x| {
+                    let p = y * width + x;
+                    let mut sum = Color::default();
+                    let mut weight_sum = 0.;
+
+                    for (ky, hy) in KERNEL.iter().enumerate() {
+                        for (kx, hx) in KERNEL.iter().enumerate() {
+                            let qx = x as i64 + (kx as i64 - 2) * step;
+                            let qy = y as i64 + (ky as i64 - 2) * step;
+                            if !(0..width as i64).contains(&qx) || !(0..height as i64).contains(&qy)
+                            {
+                                continue;
+                            }
+                            let q = qy as usize * width + qx as usize;
+
+                            let weight = hx
+                                * hy
+                                * gaussian(compress(color[p]) - compress(color[q]), color_sigma)
+                                * self.guide_weight(aovs, p, q);
+                            sum += weight * color[q];
+                            weight_sum += weight;
+                        }
+                    }
+
+                    sum / weight_sum
+                })
+            })
+            .collect()
+    }
+
+    fn guide_weight(&self, aovs: Option<&Aovs>, p: usize, q: usize) -> f64 {
+        let Some(aovs) = aovs else {
+            return 1.;
+        };
+
+        let (zp, zq) = (aovs.depth()[p], aovs.depth()[q]);
+        let depth_distance = (zp - zq).abs() / zp.max(1e-3);
+
+        gaussian(aovs.normal()[p] - aovs.normal()[q], self.normal_sigma)
+            * gaussian(aovs.albedo()[p] - aovs.albedo()[q], self.albedo_sigma)
+            * (-depth_distance * depth_distance / (self.depth_sigma * self.depth_sigma)).exp()
+    }
+}
+
+fn gaussian(difference: Vec3, sigma: f64) -> f64 {
+    (-difference.length_squared() / (sigma * sigma)).exp()
+}
+
+/**
+ * Maps radiance into [0, 1) so the color weight is not thrown off by very
+ * bright pixels.
+ */
+fn compress(c: Color) -> Color {
+    Color::new(c[0] / (1. + c[0]), c[1] / (1. + c[1]), c[2] / (1. + c[2]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::AovSample;
+    use crate::{random_double, seed_random};
+
+    #[test]
+    fn test_denoise_keeps_edges() {
+        seed_random(1);
+        let (width, height) = (32, 16);
+
+        // The left half is a dim red wall facing the camera, the right half a
+        // bright white floor facing up, both with lots of noise on top.
+        let left = |x: usize| x < width / 2;
+        let mut image = Framebuffer::new(width, height);
+        let mut samples = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let noise = 0.5 + random_double();
+                let (albedo, normal, light) = if left(x) {
+                    (Color::new(0.8, 0.1, 0.1), Vec3::new(0., 0., 1.), 0.2)
+                } else {
+                    (Color::new(0.9, 0.9, 0.9), Vec3::new(0., 1., 0.), 1.)
+                };
+                image.set_pixel(x, y, noise * light * albedo);
+                samples.push(AovSample {
+                    depth: 5.,
+                    normal,
+                    albedo,
+                    ..Default::default()
+                });
+            }
+        }
+        image.set_aovs(Aovs::new(width, height, samples));
+
+        let denoised = Denoiser::default().denoise(&image);
+
+        let error = |image: &Framebuffer| {
+            let mut error: f64 = 0.;
+            for y in 0..height {
+                for x in 0..width {
+                    let expected = if left(x) { 0.2 * 0.8 } else { 0.9 };
+                    error = error.max((image.pixel(x, y)[0] - expected).abs());
+                }
+            }
+            error
+        };
+
+        assert!(error(&image) > 0.3);
+        assert!(error(&denoised) < 0.1);
+    }
+}
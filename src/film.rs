@@ -0,0 +1,145 @@
+use crate::color::Color;
+use crate::filter::Filter;
+use crate::framebuffer::Framebuffer;
+
+/**
+ * Accumulates filter-weighted radiance for every pixel. Film coordinates are
+ * continuous: pixel (i, j) covers [i, i + 1) x [j, j + 1) and its center sits
+ * at (i + 0.5, j + 0.5).
+ */
+#[derive(Clone)]
+pub struct Film {
+    width: usize,
+    height: usize,
+    pub(crate) sum: Vec<Color>,
+    pub(crate) weight: Vec<f64>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            sum: vec![Color::default(); width * height],
+            weight: vec![0.; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /**
+     * Creates a tile for samples taken inside the pixels [x0, x1) x [y0, y1).
+     * The tile grows by the filter radius so it also holds the splats that
+     * spill over into neighbouring pixels.
+     */
+    pub fn tile(&self, x0: usize, y0: usize, x1: usize, y1: usize, radius: f64) -> FilmTile {
+        let overlap = (radius - 0.5).max(0.).ceil() as usize;
+
+        FilmTile::new(
+            x0.saturating_sub(overlap),
+            y0.saturating_sub(overlap),
+            (x1 + overlap).min(self.width),
+            (y1 + overlap).min(self.height),
+        )
+    }
+
+    pub fn merge_tile(&mut self, tile: &FilmTile) {
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                let film_index = y * self.width + x;
+                let tile_index = tile.index(x, y);
+                self.sum[film_index] += tile.sum[tile_index];
+                self.weight[film_index] += tile.weight[tile_index];
+            }
+        }
+    }
+
+    /**
+     * Divides the accumulated radiance by the accumulated filter weight.
+     * Pixels without any weight stay black.
+     */
+    pub fn resolve(&self) -> Framebuffer {
+        let pixels = self
+            .sum
+            .iter()
+            .zip(&self.weight)
+            .map(|(&sum, &weight)| {
+                if weight != 0. {
+                    sum / weight
+                } else {
+                    Color::default()
+                }
+            })
+            .collect();
+
+        Framebuffer::from_pixels(self.width, self.height, pixels)
+    }
+}
+
+/**
+ * A rectangular piece of the film one thread accumulates into before it is
+ * merged back into the film.
+ */
+#[derive(Clone)]
+pub struct FilmTile {
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+    sum: Vec<Color>,
+    weight: Vec<f64>,
+}
+
+impl FilmTile {
+    fn new(x0: usize, y0: usize, x1: usize, y1: usize) -> Self {
+        let size = (x1 - x0) * (y1 - y0);
+        Self {
+            x0,
+            y0,
+            x1,
+            y1,
+            sum: vec![Color::default(); size],
+            weight: vec![0.; size],
+        }
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        (y - self.y0) * (self.x1 - self.x0) + (x - self.x0)
+    }
+
+    /**
+     * Splats a radiance sample at the film position (film_x, film_y) onto
+     * every pixel of the tile within the filter radius.
+     */
+    pub fn add_sample(&mut self, film_x: f64, film_y: f64, color: Color, filter: &dyn Filter) {
+        let radius = filter.radius();
+
+        // Discrete pixel coordinates have their centers at integers.
+        let dx = film_x - 0.5;
+        let dy = film_y - 0.5;
+
+        let x_start = ((dx - radius).ceil().max(self.x0 as f64)) as usize;
+        let y_start = ((dy - radius).ceil().max(self.y0 as f64)) as usize;
+        let x_end = ((dx + radius).floor() + 1.).min(self.x1 as f64).max(0.) as usize;
+        let y_end = ((dy + radius).floor() + 1.).min(self.y1 as f64).max(0.) as usize;
+
+        for y in y_start..y_end {
+            for x in x_start..x_end {
+                let weight = filter.evaluate(x as f64 - dx, y as f64 - dy);
+                if weight == 0. {
+                    continue;
+                }
+
+                let index = self.index(x, y);
+                self.sum[index] += weight * color;
+                self.weight[index] += weight;
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use crate::PI;
+
+/**
+ * A pixel reconstruction filter. Every sample is splatted onto all pixels
+ * whose centers lie within `radius` of it, weighted by `evaluate` at the
+ * offset from the pixel center.
+ */
+pub trait Filter: Sync + Send {
+    fn radius(&self) -> f64;
+
+    fn evaluate(&self, x: f64, y: f64) -> f64;
+}
+
+/**
+ * Equal weight over a square; with a radius of 0.5 every sample only lands in
+ * the pixel it was taken in.
+ */
+#[derive(Clone, Copy)]
+pub struct BoxFilter {
+    radius: f64,
+}
+
+impl BoxFilter {
+    pub fn new(radius: f64) -> Self {
+        Self { radius }
+    }
+}
+
+impl Default for BoxFilter {
+    fn default() -> Self {
+        Self::new(0.5)
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        if x.abs() <= self.radius && y.abs() <= self.radius {
+            1.
+        } else {
+            0.
+        }
+    }
+}
+
+/**
+ * A separable triangle filter falling off linearly to zero at the radius.
+ */
+#[derive(Clone, Copy)]
+pub struct TentFilter {
+    radius: f64,
+}
+
+impl TentFilter {
+    pub fn new(radius: f64) -> Self {
+        Self { radius }
+    }
+}
+
+impl Default for TentFilter {
+    fn default() -> Self {
+        Self::new(1.)
+    }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        (self.radius - x.abs()).max(0.) * (self.radius - y.abs()).max(0.)
+    }
+}
+
+/**
+ * A separable Gaussian, shifted down so it reaches zero at the radius.
+ */
+#[derive(Clone, Copy)]
+pub struct GaussianFilter {
+    radius: f64,
+    sigma: f64,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: f64, sigma: f64) -> Self {
+        Self { radius, sigma }
+    }
+
+    fn gaussian(&self, x: f64) -> f64 {
+        let g = |x: f64| (-x * x / (2. * self.sigma * self.sigma)).exp();
+        (g(x) - g(self.radius)).max(0.)
+    }
+}
+
+impl Default for GaussianFilter {
+    fn default() -> Self {
+        Self::new(1.5, 0.5)
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.gaussian(x) * self.gaussian(y)
+    }
+}
+
+/**
+ * The separable Mitchell-Netravali cubic with parameters `b` and `c`.
+ */
+#[derive(Clone, Copy)]
+pub struct MitchellFilter {
+    radius: f64,
+    b: f64,
+    c: f64,
+}
+
+impl MitchellFilter {
+    pub fn new(radius: f64, b: f64, c: f64) -> Self {
+        Self { radius, b, c }
+    }
+
+    fn mitchell(&self, x: f64) -> f64 {
+        // The cubic is defined over [-2, 2], scale it to the radius.
+        let x = (2. * x / self.radius).abs();
+        let (b, c) = (self.b, self.c);
+
+        let value = if x > 2. {
+            0.
+        } else if x > 1. {
+            (-b - 6. * c) * x.powi(3)
+                + (6. * b + 30. * c) * x.powi(2)
+                + (-12. * b - 48. * c) * x
+                + (8. * b + 24. * c)
+        } else {
+            (12. - 9. * b - 6. * c) * x.powi(3)
+                + (-18. + 12. * b + 6. * c) * x.powi(2)
+                + (6. - 2. * b)
+        };
+
+        value / 6.
+    }
+}
+
+impl Default for MitchellFilter {
+    fn default() -> Self {
+        Self::new(2., 1. / 3., 1. / 3.)
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.mitchell(x) * self.mitchell(y)
+    }
+}
+
+/**
+ * A separable Lanczos windowed sinc with `tau` lobes.
+ */
+#[derive(Clone, Copy)]
+pub struct LanczosFilter {
+    radius: f64,
+    tau: f64,
+}
+
+impl LanczosFilter {
+    pub fn new(radius: f64, tau: f64) -> Self {
+        Self { radius, tau }
+    }
+
+    fn sinc(x: f64) -> f64 {
+        if x.abs() < 1e-5 {
+            1.
+        } else {
+            (PI * x).sin() / (PI * x)
+        }
+    }
+
+    fn windowed_sinc(&self, x: f64) -> f64 {
+        if x.abs() > self.radius {
+            return 0.;
+        }
+
+        Self::sinc(x) * Self::sinc(x / self.tau)
+    }
+}
+
+impl Default for LanczosFilter {
+    fn default() -> Self {
+        Self::new(2., 2.)
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.windowed_sinc(x) * self.windowed_sinc(y)
+    }
+}
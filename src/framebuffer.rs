@@ -0,0 +1,183 @@
+use crate::aov::Aovs;
+use crate::color::Color;
+use crate::tonemap::DisplayTransform;
+
+/**
+ * A rendered image held in memory as linear radiance, one color per pixel,
+ * stored row by row starting from the top-left corner. The display transform
+ * is used by writers of 8 and 16-bit formats.
+ */
+#[derive(Clone, Default)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    display: DisplayTransform,
+    sample_counts: Option<Vec<u32>>,
+    aovs: Option<Aovs>,
+    heatmap_scale: Option<f64>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::default(); width * height],
+            display: DisplayTransform::default(),
+            sample_counts: None,
+            aovs: None,
+            heatmap_scale: None,
+        }
+    }
+
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert_eq!(
+            pixels.len(),
+            width * height,
+            "pixel count does not match the image size"
+        );
+        Self {
+            width,
+            height,
+            pixels,
+            display: DisplayTransform::default(),
+            sample_counts: None,
+            aovs: None,
+            heatmap_scale: None,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn display(&self) -> &DisplayTransform {
+        &self.display
+    }
+
+    pub fn set_display(&mut self, display: DisplayTransform) {
+        self.display = display;
+    }
+
+    /**
+     * The number of samples taken in every pixel, kept by adaptive sampling.
+     */
+    pub fn sample_counts(&self) -> Option<&[u32]> {
+        self.sample_counts.as_deref()
+    }
+
+    pub fn set_sample_counts(&mut self, sample_counts: Vec<u32>) {
+        assert_eq!(sample_counts.len(), self.pixels.len());
+        self.sample_counts = Some(sample_counts);
+    }
+
+    /**
+     * The sample counts as a grayscale image, scaled so the most sampled
+     * pixel is white.
+     */
+    pub fn sample_count_image(&self) -> Option<Framebuffer> {
+        let counts = self.sample_counts()?;
+        let max = counts.iter().copied().max().unwrap_or(0).max(1) as f64;
+        let pixels = counts
+            .iter()
+            .map(|&count| {
+                let value = count as f64 / max;
+                Color::new(value, value, value)
+            })
+            .collect();
+
+        Some(Framebuffer::from_pixels(self.width, self.height, pixels))
+    }
+
+    /**
+     * The first-hit AOVs, if the camera was asked to render them.
+     */
+    pub fn aovs(&self) -> Option<&Aovs> {
+        self.aovs.as_ref()
+    }
+
+    pub fn set_aovs(&mut self, aovs: Aovs) {
+        assert_eq!((aovs.width(), aovs.height()), (self.width, self.height));
+        self.aovs = Some(aovs);
+    }
+
+    pub fn clear_aovs(&mut self) {
+        self.aovs = None;
+    }
+
+    /**
+     * For a heatmap, the per-sample value shown at the hot end of the false
+     * color scale.
+     */
+    pub fn heatmap_scale(&self) -> Option<f64> {
+        self.heatmap_scale
+    }
+
+    pub fn set_heatmap_scale(&mut self, scale: f64) {
+        self.heatmap_scale = Some(scale);
+    }
+
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [Color] {
+        &mut self.pixels
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    /**
+     * A copy of the pixels [x0, x1) x [y0, y1), along with their sample counts.
+     */
+    pub fn crop(&self, x0: usize, y0: usize, x1: usize, y1: usize) -> Framebuffer {
+        assert!(x0 <= x1 && x1 <= self.width && y0 <= y1 && y1 <= self.height);
+        let (width, height) = (x1 - x0, y1 - y0);
+        let index = |k: usize| (y0 + k / width.max(1)) * self.width + x0 + k % width.max(1);
+        let pixels = (0..width * height).map(|k| self.pixels[index(k)]).collect();
+
+        let mut image = Framebuffer::from_pixels(width, height, pixels);
+        image.display = self.display.clone();
+        image.heatmap_scale = self.heatmap_scale;
+        image.sample_counts = self
+            .sample_counts
+            .as_ref()
+            .map(|counts| (0..width * height).map(|k| counts[index(k)]).collect());
+
+        image
+    }
+
+    /**
+     * Copies the pixels of `image` over this one with its top-left corner at
+     * (x, y). The sample counts are copied too when both images have them.
+     */
+    pub fn insert(&mut self, x: usize, y: usize, image: &Framebuffer) {
+        assert!(x + image.width <= self.width && y + image.height <= self.height);
+
+        for (row, pixels) in image.rows().enumerate() {
+            let start = (y + row) * self.width + x;
+            self.pixels[start..start + image.width].copy_from_slice(pixels);
+        }
+        if let (Some(counts), Some(source)) = (&mut self.sample_counts, image.sample_counts()) {
+            for (row, source) in source.chunks(image.width.max(1)).enumerate() {
+                let start = (y + row) * self.width + x;
+                counts[start..start + image.width].copy_from_slice(source);
+            }
+        }
+    }
+
+    pub fn rows(&self) -> impl DoubleEndedIterator<Item = &[Color]> {
+        self.pixels.chunks(self.width.max(1))
+    }
+}
@@ -0,0 +1,96 @@
+use crate::color::{srgb_to_linear, Color};
+use crate::framebuffer::Framebuffer;
+use crate::stats::Trace;
+
+/// The false color scale from cold to hot, display-encoded.
+const SCALE: [(f64, f64, f64); 6] = [
+    (0., 0., 0.),
+    (0.1, 0.1, 0.7),
+    (0., 0.7, 0.9),
+    (0.2, 0.85, 0.2),
+    (1., 0.9, 0.),
+    (0.9, 0.1, 0.05),
+];
+
+/**
+ * A debug render that shows how much work every pixel took instead of its
+ * radiance. The values are averaged over the samples of each pixel and shown
+ * in false color, scaled so the most expensive pixel is at the hot end. The
+ * value at the hot end is kept as the `heatmap_scale` of the image.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Heatmap {
+    /// BVH nodes visited per camera sample.
+    BvhNodes,
+    /// Primitive intersection tests per camera sample.
+    IntersectionTests,
+    /// Bounces a path took before it escaped, was absorbed or hit the depth
+    /// limit.
+    PathLength,
+}
+
+impl Heatmap {
+    pub(crate) fn value(&self, trace: &Trace) -> f64 {
+        match self {
+            Heatmap::BvhNodes => trace.bvh_nodes as f64,
+            Heatmap::IntersectionTests => trace.intersection_tests as f64,
+            Heatmap::PathLength => trace.bounces as f64,
+        }
+    }
+
+    /**
+     * Replaces the values of an image rendered in this mode, stored in every
+     * channel, by their false colors, and returns the value at the hot end of
+     * the scale.
+     */
+    pub(crate) fn colorize(&self, image: &mut Framebuffer) -> f64 {
+        let value = |pixel: &Color| (pixel[0] + pixel[1] + pixel[2]) / 3.;
+        let max = image.pixels().iter().map(value).fold(0., f64::max);
+
+        for pixel in image.pixels_mut() {
+            let t = if max > 0. { value(pixel) / max } else { 0. };
+            *pixel = false_color(t);
+        }
+
+        max
+    }
+}
+
+/**
+ * The linear color for `t` in [0, 1] on a scale running from black through
+ * blue, cyan, green and yellow to red.
+ */
+pub fn false_color(t: f64) -> Color {
+    let x = t.clamp(0., 1.) * (SCALE.len() - 1) as f64;
+    let k = (x.floor() as usize).min(SCALE.len() - 2);
+    let f = x - k as f64;
+    let (a, b) = (SCALE[k], SCALE[k + 1]);
+    let mix = |a: f64, b: f64| srgb_to_linear(a + f * (b - a));
+
+    Color::new(mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colorize_scales_to_max() {
+        let mut image = Framebuffer::from_pixels(
+            3,
+            1,
+            vec![
+                Color::new(0., 0., 0.),
+                Color::new(10., 10., 10.),
+                Color::new(20., 20., 20.),
+            ],
+        );
+
+        assert_eq!(Heatmap::BvhNodes.colorize(&mut image), 20.);
+        assert!(image.pixel(0, 0) == Color::new(0., 0., 0.));
+        assert!(image.pixel(1, 0) == false_color(0.5));
+        assert!(image.pixel(2, 0) == false_color(1.));
+        // The hot end is red.
+        assert!(image.pixel(2, 0)[0] > 0.7 && image.pixel(2, 0)[1] < 0.05);
+    }
+}
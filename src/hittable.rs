@@ -1,11 +1,12 @@
-use ray_tracing::{degrees_to_radians, INFINITY};
+use crate::{degrees_to_radians, mix_seed, INFINITY};
 
 use crate::aabb::*;
+use crate::hittable_list::HittableList;
 use crate::interval::Interval;
 use crate::material::{Dielectric, Material};
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::vec3::{Point3, Vec3};
-use std::f64::NEG_INFINITY;
 use std::sync::Arc;
 
 #[derive(Clone)]
@@ -17,6 +18,7 @@ pub struct HitRecord {
     pub(crate) u: f64,
     pub(crate) v: f64,
     pub(crate) front_face: bool,
+    pub(crate) object_id: u64,
 }
 
 impl HitRecord {
@@ -35,6 +37,7 @@ impl HitRecord {
             u: 0.,
             v: 0.,
             front_face,
+            object_id: 0,
         }
     }
     /**
@@ -62,14 +65,36 @@ impl Default for HitRecord {
             u: 0.,
             v: 0.,
             front_face: false,
+            object_id: 0,
         }
     }
 }
 
-pub trait Hittable: Sync + HittableClone {
+pub trait Hittable: Send + Sync + HittableClone {
     fn hit(&self, r: Ray, ray_t: Interval, rec: &mut HitRecord) -> bool;
 
     fn bounding_box(&self) -> Aabb;
+
+    /**
+     * The density, with respect to solid angle, of `random` choosing
+     * `direction` from `origin`. Zero for objects that cannot be sampled.
+     */
+    fn pdf_value(&self, _origin: Point3, _direction: Vec3) -> f64 {
+        0.
+    }
+
+    /**
+     * A direction from `origin` towards a random point on the object.
+     */
+    fn random(&self, _origin: Point3, _sampler: &mut dyn Sampler) -> Vec3 {
+        Vec3::new(1., 0., 0.)
+    }
+
+    /**
+     * Adds the emissive primitives of the object to `lights`, placed as they
+     * are in the scene.
+     */
+    fn collect_lights(&self, _lights: &mut HittableList) {}
 }
 
 pub trait HittableClone {
@@ -91,11 +116,60 @@ impl Clone for Box<dyn Hittable> {
     }
 }
 
+/**
+ * Lets several scenes share one object, such as a BVH over the static part of
+ * an animation, without copying it.
+ */
+impl Hittable for Arc<dyn Hittable> {
+    fn hit(&self, r: Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        (**self).hit(r, ray_t, rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        (**self).bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        (**self).pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        (**self).random(origin, sampler)
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        (**self).collect_lights(lights)
+    }
+}
+
+/**
+ * Hashes the values that define an object into an ID that stays the same
+ * from run to run, no matter how the scene is laid out in memory.
+ */
+pub(crate) fn object_id(values: &[f64]) -> u64 {
+    values
+        .iter()
+        .fold(0, |id, value| mix_seed(id, value.to_bits()))
+}
+
+/**
+ * An instance counts as a single object, identified by its transform and the
+ * bounds of what it wraps.
+ */
+fn instance_id(transform: &[f64], object: &dyn Hittable) -> u64 {
+    let bbox = object.bounding_box();
+    let bounds = [
+        bbox.x.min, bbox.x.max, bbox.y.min, bbox.y.max, bbox.z.min, bbox.z.max,
+    ];
+    object_id(&[transform, &bounds].concat())
+}
+
 #[derive(Clone)]
 pub struct Translate {
     object: Box<dyn Hittable>,
     offset: Vec3,
     bbox: Aabb,
+    id: u64,
 }
 
 impl Translate {
@@ -105,6 +179,7 @@ impl Translate {
             object,
             offset: displacement,
             bbox: p.bounding_box() + displacement,
+            id: instance_id(&[displacement[0], displacement[1], displacement[2]], &*p),
         }
     }
 }
@@ -118,6 +193,7 @@ impl Hittable for Translate {
         }
 
         rec.p += self.offset;
+        rec.object_id = self.id;
 
         true
     }
@@ -125,14 +201,32 @@ impl Hittable for Translate {
     fn bounding_box(&self) -> Aabb {
         self.bbox
     }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.object.pdf_value(origin - self.offset, direction)
+    }
+
+    fn random(&self, origin: Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.object.random(origin - self.offset, sampler)
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        let mut object_lights = HittableList::default();
+        self.object.collect_lights(&mut object_lights);
+        for light in object_lights.objects {
+            lights.add(Box::new(Translate::new(light, self.offset)));
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct RotateY {
     object: Box<dyn Hittable>,
+    angle: f64,
     sin_theta: f64,
     cos_theta: f64,
     bbox: Aabb,
+    id: u64,
 }
 
 impl RotateY {
@@ -141,11 +235,12 @@ impl RotateY {
         let sin_theta = radians.sin();
         let cos_theta = radians.cos();
         let mut bbox = p.bounding_box();
+        let id = instance_id(&[angle], &*p);
 
         let object = p.clone();
 
         let mut min = Point3::new(INFINITY, INFINITY, INFINITY);
-        let mut max = Point3::new(NEG_INFINITY, NEG_INFINITY, NEG_INFINITY);
+        let mut max = Point3::new(-INFINITY, -INFINITY, -INFINITY);
 
         for i in 0..2 {
             for j in 0..2 {
@@ -171,40 +266,46 @@ impl RotateY {
 
         Self {
             object,
+            angle,
             sin_theta,
             cos_theta,
             bbox,
+            id,
         }
     }
+
+    fn to_object(&self, a: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * a[0] - self.sin_theta * a[2],
+            a[1],
+            self.sin_theta * a[0] + self.cos_theta * a[2],
+        )
+    }
+
+    fn to_world(&self, a: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * a[0] + self.sin_theta * a[2],
+            a[1],
+            -self.sin_theta * a[0] + self.cos_theta * a[2],
+        )
+    }
 }
 
 impl Hittable for RotateY {
     fn hit(&self, r: Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
-        let mut origin = r.origin();
-        let mut direction = r.direction();
-
-        origin[0] = self.cos_theta * r.origin()[0] - self.sin_theta * r.origin()[2];
-        origin[2] = self.sin_theta * r.origin()[0] + self.cos_theta * r.origin()[2];
-
-        direction[0] = self.cos_theta * r.direction()[0] - self.sin_theta * r.direction()[2];
-        direction[2] = self.sin_theta * r.direction()[0] + self.cos_theta * r.direction()[2];
-
-        let rotated_r = Ray::new_with_time(origin, direction, r.time());
+        let rotated_r = Ray::new_with_time(
+            self.to_object(r.origin()),
+            self.to_object(r.direction()),
+            r.time(),
+        );
 
         if !self.object.hit(rotated_r, ray_t, rec) {
             return false;
         }
 
-        let mut p = rec.p;
-        p[0] = self.cos_theta * rec.p[0] + self.sin_theta * rec.p[2];
-        p[2] = -self.sin_theta * rec.p[0] + self.cos_theta * rec.p[2];
-
-        let mut normal = rec.normal;
-        normal[0] = self.cos_theta * rec.normal[0] + self.sin_theta * rec.normal[2];
-        normal[2] = -self.sin_theta * rec.normal[0] + self.cos_theta * rec.normal[2];
-
-        rec.p = p;
-        rec.normal = normal;
+        rec.p = self.to_world(rec.p);
+        rec.normal = self.to_world(rec.normal);
+        rec.object_id = self.id;
 
         true
     }
@@ -212,4 +313,21 @@ impl Hittable for RotateY {
     fn bounding_box(&self) -> Aabb {
         self.bbox
     }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.object
+            .pdf_value(self.to_object(origin), self.to_object(direction))
+    }
+
+    fn random(&self, origin: Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.to_world(self.object.random(self.to_object(origin), sampler))
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        let mut object_lights = HittableList::default();
+        self.object.collect_lights(&mut object_lights);
+        for light in object_lights.objects {
+            lights.add(Box::new(RotateY::new(light, self.angle)));
+        }
+    }
 }
@@ -2,6 +2,8 @@ use crate::aabb::*;
 use crate::hittable::{HitRecord, Hittable};
 use crate::interval::Interval;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::{Point3, Vec3};
 
 #[derive(Clone, Default)]
 pub struct HittableList {
@@ -47,4 +49,36 @@ impl Hittable for HittableList {
     fn bounding_box(&self) -> crate::aabb::Aabb {
         self.bbox
     }
+
+    /**
+     * Samples every object with equal probability.
+     */
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.;
+        }
+        let sum: f64 = self
+            .objects
+            .iter()
+            .map(|object| object.pdf_value(origin, direction))
+            .sum();
+
+        sum / self.objects.len() as f64
+    }
+
+    fn random(&self, origin: Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        if self.objects.is_empty() {
+            return Vec3::new(1., 0., 0.);
+        }
+        let n = self.objects.len();
+        let k = ((sampler.get_1d() * n as f64) as usize).min(n - 1);
+
+        self.objects[k].random(origin, sampler)
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        for object in &self.objects {
+            object.collect_lights(lights);
+        }
+    }
 }
@@ -0,0 +1,575 @@
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::interval::Interval;
+use crate::material::{Lobe, ScatterRecord};
+use crate::mis::LightSampling;
+use crate::pdf::{CosinePdf, HittablePdf, Pdf};
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::stats;
+use crate::vec3::{Point3, Vec3};
+use crate::INFINITY;
+use std::sync::Arc;
+
+/**
+ * The world being rendered along with its lights, gathered once per render.
+ */
+pub struct Scene<'a> {
+    world: &'a dyn Hittable,
+    lights: HittableList,
+    background: Color,
+}
+
+impl<'a> Scene<'a> {
+    pub fn new(world: &'a dyn Hittable, background: Color) -> Self {
+        let mut lights = HittableList::default();
+        world.collect_lights(&mut lights);
+
+        Self {
+            world,
+            lights,
+            background,
+        }
+    }
+
+    pub fn world(&self) -> &dyn Hittable {
+        self.world
+    }
+
+    /**
+     * The emissive primitives of the world, placed as they are in it.
+     */
+    pub fn lights(&self) -> &HittableList {
+        &self.lights
+    }
+
+    /**
+     * The radiance of rays that leave the world.
+     */
+    pub fn background(&self) -> Color {
+        self.background
+    }
+
+    fn hit(&self, r: Ray, rec: &mut HitRecord) -> bool {
+        self.world.hit(r, Interval::new(0.001, INFINITY), rec)
+    }
+}
+
+/**
+ * An algorithm turning camera rays into the light they carry, which the
+ * camera runs for every sample.
+ */
+pub trait Integrator: Send + Sync {
+    /**
+     * The radiance arriving at the camera along `r`.
+     */
+    fn radiance(&self, r: Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color;
+}
+
+/**
+ * The integrator a camera renders with.
+ */
+#[derive(Clone, Default)]
+pub enum IntegratorType {
+    /// Full global illumination with `PathIntegrator`.
+    #[default]
+    Path,
+    /// Only the light reaching the first hit straight from the lights.
+    DirectLighting,
+    /// Light sampled at diffuse hits, with mirrors and glass followed up to
+    /// `max_depth`.
+    Whitted,
+    /// How open the surface is within `radius`, from white to black.
+    AmbientOcclusion { radius: f64 },
+    /// The shading normals at the first hit, mapped to colors.
+    Normals,
+    /// The base colors at the first hit.
+    Albedo,
+    /// An integrator from outside the crate.
+    Custom(Arc<dyn Integrator>),
+}
+
+impl IntegratorType {
+    /**
+     * The integrator with the path settings of `camera`.
+     */
+    pub(crate) fn create(&self, camera: &Camera) -> Arc<dyn Integrator> {
+        match self {
+            IntegratorType::Path => Arc::new(PathIntegrator {
+                max_depth: camera.max_depth,
+                russian_roulette: camera.russian_roulette,
+                light_sampling: camera.light_sampling,
+            }),
+            IntegratorType::DirectLighting => Arc::new(DirectLighting {
+                light_sampling: camera.light_sampling,
+            }),
+            IntegratorType::Whitted => Arc::new(Whitted {
+                max_depth: camera.max_depth,
+            }),
+            IntegratorType::AmbientOcclusion { radius } => {
+                Arc::new(AmbientOcclusion { radius: *radius })
+            }
+            IntegratorType::Normals => Arc::new(Normals),
+            IntegratorType::Albedo => Arc::new(Albedo),
+            IntegratorType::Custom(integrator) => integrator.clone(),
+        }
+    }
+
+    pub(crate) fn parameters(&self) -> [f64; 2] {
+        match *self {
+            IntegratorType::Path => [0., 0.],
+            IntegratorType::DirectLighting => [1., 0.],
+            IntegratorType::Whitted => [2., 0.],
+            IntegratorType::AmbientOcclusion { radius } => [3., radius],
+            IntegratorType::Normals => [4., 0.],
+            IntegratorType::Albedo => [5., 0.],
+            IntegratorType::Custom(_) => [6., 0.],
+        }
+    }
+}
+
+/**
+ * A path tracer following one scattered ray per bounce, with the light at
+ * diffuse and glossy hits also sampled directly.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathIntegrator {
+    pub max_depth: i32,
+    /// Paths that bounced this often may end early, with a chance that grows
+    /// as their throughput drops.
+    pub russian_roulette: Option<i32>,
+    pub light_sampling: LightSampling,
+}
+
+impl PathIntegrator {
+    /**
+     * Plays Russian roulette for a path after `bounces` bounces, returning
+     * the chance it survived with, or `None` if it ends here. Paths keep
+     * going with a chance of their largest throughput component, and the
+     * survivors are weighted up by it so the estimate stays unbiased.
+     */
+    fn survival(
+        &self,
+        bounces: usize,
+        throughput: Color,
+        sampler: &mut dyn Sampler,
+    ) -> Option<f64> {
+        match self.russian_roulette {
+            Some(min_depth) if bounces as i32 >= min_depth => {
+                let survival = throughput[0].max(throughput[1]).max(throughput[2]).min(1.);
+                (survival > 0. && sampler.get_1d() < survival).then_some(survival)
+            }
+            _ => Some(1.),
+        }
+    }
+}
+
+impl Integrator for PathIntegrator {
+    /**
+     * At diffuse and glossy hits the light of `scene.lights()` is both
+     * sampled directly and found by the scattered ray, which counts the
+     * emission it hits scaled by `emission_weight`, so that the two
+     * strategies together count it once.
+     */
+    fn radiance(&self, r: Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color {
+        let mut radiance = Color::default();
+        let mut throughput = Color::new(1., 1., 1.);
+        let mut emission_weight = 1.;
+        let mut r = r;
+
+        for bounces in 0.. {
+            if bounces as i32 >= self.max_depth {
+                stats::path(bounces);
+                break;
+            }
+
+            if bounces == 0 {
+                stats::camera_ray();
+            } else {
+                stats::secondary_ray();
+            }
+            let mut rec = HitRecord::default();
+            if !scene.hit(r, &mut rec) {
+                stats::path(bounces);
+                radiance += throughput * scene.background;
+                break;
+            }
+
+            if emission_weight > 0. {
+                radiance += emission_weight * throughput * rec.mat.emitted(rec.u, rec.v, rec.p);
+            }
+
+            let mut srec = ScatterRecord::default();
+            if !rec.mat.scatter(&r, &rec, &mut srec, sampler) {
+                stats::path(bounces);
+                break;
+            }
+
+            match srec.lobe {
+                Lobe::Specular(scattered) => {
+                    throughput = throughput * srec.attenuation;
+                    emission_weight = 1.;
+                    r = scattered;
+                }
+                Lobe::Diffuse(pdf) => {
+                    radiance += throughput
+                        * srec.attenuation
+                        * sample_light(&r, &rec, &*pdf, scene, sampler, self.light_sampling);
+
+                    let scattered = Ray::new_with_time(rec.p, pdf.generate(sampler), r.time());
+                    let pdf_value = pdf.value(scattered.direction());
+                    let scattering_pdf = rec.mat.scattering_pdf(&r, &rec, &scattered);
+                    if pdf_value <= 0. || scattering_pdf <= 0. {
+                        stats::path(bounces);
+                        break;
+                    }
+                    throughput = throughput * (scattering_pdf / pdf_value) * srec.attenuation;
+                    emission_weight = found_light_weight(
+                        rec.p,
+                        scattered.direction(),
+                        pdf_value,
+                        scene,
+                        self.light_sampling,
+                    );
+                    r = scattered;
+                }
+            }
+
+            let Some(survival) = self.survival(bounces, throughput, sampler) else {
+                stats::path(bounces);
+                break;
+            };
+            throughput = throughput / survival;
+        }
+
+        radiance
+    }
+}
+
+/**
+ * The light reaching the first hit straight from the lights, combining light
+ * and scattering samples as `light_sampling` says. Mirrors and glass show no
+ * light.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirectLighting {
+    pub light_sampling: LightSampling,
+}
+
+impl Integrator for DirectLighting {
+    fn radiance(&self, r: Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color {
+        stats::camera_ray();
+        let mut rec = HitRecord::default();
+        if !scene.hit(r, &mut rec) {
+            stats::path(0);
+            return scene.background;
+        }
+
+        let emitted = rec.mat.emitted(rec.u, rec.v, rec.p);
+        let mut srec = ScatterRecord::default();
+        if !rec.mat.scatter(&r, &rec, &mut srec, sampler) {
+            stats::path(0);
+            return emitted;
+        }
+        let Lobe::Diffuse(pdf) = srec.lobe else {
+            stats::path(0);
+            return emitted;
+        };
+
+        let direct =
+            srec.attenuation * sample_light(&r, &rec, &*pdf, scene, sampler, self.light_sampling);
+
+        let scattered = Ray::new_with_time(rec.p, pdf.generate(sampler), r.time());
+        let pdf_value = pdf.value(scattered.direction());
+        let scattering_pdf = rec.mat.scattering_pdf(&r, &rec, &scattered);
+        if pdf_value <= 0. || scattering_pdf <= 0. {
+            stats::path(0);
+            return emitted + direct;
+        }
+
+        stats::secondary_ray();
+        stats::path(1);
+        let mut light_rec = HitRecord::default();
+        let found = if scene.hit(scattered, &mut light_rec) {
+            let weight = found_light_weight(
+                rec.p,
+                scattered.direction(),
+                pdf_value,
+                scene,
+                self.light_sampling,
+            );
+            weight * light_rec.mat.emitted(light_rec.u, light_rec.v, light_rec.p)
+        } else {
+            scene.background
+        };
+
+        emitted + direct + (scattering_pdf / pdf_value) * srec.attenuation * found
+    }
+}
+
+/**
+ * Classic ray tracing: lights are sampled at diffuse and glossy hits, while
+ * mirrors and glass are followed for up to `max_depth` rays.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Whitted {
+    pub max_depth: i32,
+}
+
+impl Integrator for Whitted {
+    fn radiance(&self, r: Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color {
+        let mut radiance = Color::default();
+        let mut throughput = Color::new(1., 1., 1.);
+        let mut r = r;
+
+        for bounces in 0..self.max_depth.max(0) as usize {
+            if bounces == 0 {
+                stats::camera_ray();
+            } else {
+                stats::secondary_ray();
+            }
+            let mut rec = HitRecord::default();
+            if !scene.hit(r, &mut rec) {
+                stats::path(bounces);
+                return radiance + throughput * scene.background;
+            }
+
+            radiance += throughput * rec.mat.emitted(rec.u, rec.v, rec.p);
+            let mut srec = ScatterRecord::default();
+            if !rec.mat.scatter(&r, &rec, &mut srec, sampler) {
+                stats::path(bounces);
+                return radiance;
+            }
+
+            match srec.lobe {
+                Lobe::Specular(scattered) => {
+                    throughput = throughput * srec.attenuation;
+                    r = scattered;
+                }
+                Lobe::Diffuse(pdf) => {
+                    stats::path(bounces);
+                    return radiance
+                        + throughput
+                            * srec.attenuation
+                            * sample_light(&r, &rec, &*pdf, scene, sampler, LightSampling::Lights);
+                }
+            }
+        }
+
+        stats::path(self.max_depth.max(0) as usize);
+        radiance
+    }
+}
+
+/**
+ * The fraction of cosine-weighted directions above the first hit that leave
+ * it without hitting anything within `radius`.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AmbientOcclusion {
+    pub radius: f64,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(&self, r: Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color {
+        stats::camera_ray();
+        let mut rec = HitRecord::default();
+        if !scene.hit(r, &mut rec) {
+            stats::path(0);
+            return Color::default();
+        }
+
+        let direction = CosinePdf::new(rec.normal).generate(sampler);
+        stats::secondary_ray();
+        stats::path(1);
+        let mut occluder = HitRecord::default();
+        if scene.world.hit(
+            Ray::new_with_time(rec.p, direction, r.time()),
+            Interval::new(0.001, self.radius / direction.length()),
+            &mut occluder,
+        ) {
+            Color::default()
+        } else {
+            Color::new(1., 1., 1.)
+        }
+    }
+}
+
+/**
+ * The shading normal at the first hit, with each component mapped from
+ * [-1, 1] to [0, 1].
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Normals;
+
+impl Integrator for Normals {
+    fn radiance(&self, r: Ray, scene: &Scene, _sampler: &mut dyn Sampler) -> Color {
+        stats::camera_ray();
+        stats::path(0);
+        let mut rec = HitRecord::default();
+        if !scene.hit(r, &mut rec) {
+            return Color::default();
+        }
+
+        0.5 * (rec.normal + Color::new(1., 1., 1.))
+    }
+}
+
+/**
+ * The base color of the first hit, or the background.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Albedo;
+
+impl Integrator for Albedo {
+    fn radiance(&self, r: Ray, scene: &Scene, _sampler: &mut dyn Sampler) -> Color {
+        stats::camera_ray();
+        stats::path(0);
+        let mut rec = HitRecord::default();
+        if !scene.hit(r, &mut rec) {
+            return scene.background;
+        }
+
+        rec.mat.albedo(&rec)
+    }
+}
+
+fn samples_lights(scene: &Scene, light_sampling: LightSampling) -> bool {
+    light_sampling != LightSampling::Bsdf && !scene.lights.objects.is_empty()
+}
+
+/**
+ * Estimates the light arriving at a diffuse or glossy hit directly from the
+ * lights of the scene, weighted by the scattering density of the surface, by
+ * sampling a point on a light and casting a shadow ray to it. `bsdf_pdf` is
+ * the density the scattered ray is drawn with.
+ */
+fn sample_light(
+    r_in: &Ray,
+    rec: &HitRecord,
+    bsdf_pdf: &dyn Pdf,
+    scene: &Scene,
+    sampler: &mut dyn Sampler,
+    light_sampling: LightSampling,
+) -> Color {
+    if !samples_lights(scene, light_sampling) {
+        return Color::default();
+    }
+
+    let light_pdf = HittablePdf::new(&scene.lights, rec.p);
+    let shadow = Ray::new_with_time(rec.p, light_pdf.generate(sampler), r_in.time());
+    let pdf_value = light_pdf.value(shadow.direction());
+    let scattering_pdf = rec.mat.scattering_pdf(r_in, rec, &shadow);
+    if pdf_value <= 0. || scattering_pdf <= 0. {
+        return Color::default();
+    }
+
+    stats::secondary_ray();
+    let mut light_rec = HitRecord::default();
+    if !scene.hit(shadow, &mut light_rec) {
+        return Color::default();
+    }
+    let emitted = light_rec.mat.emitted(light_rec.u, light_rec.v, light_rec.p);
+    let weight = light_sampling.light_weight(pdf_value, bsdf_pdf.value(shadow.direction()));
+
+    (weight * scattering_pdf / pdf_value) * emitted
+}
+
+/**
+ * The weight of the light a ray scattered from `origin` in `direction` finds
+ * on its own, drawn with density `bsdf_pdf`, next to the light that
+ * `sample_light` sampled at the same hit.
+ */
+fn found_light_weight(
+    origin: Point3,
+    direction: Vec3,
+    bsdf_pdf: f64,
+    scene: &Scene,
+    light_sampling: LightSampling,
+) -> f64 {
+    let light_pdf = if samples_lights(scene, light_sampling) {
+        scene.lights.pdf_value(origin, direction)
+    } else {
+        0.
+    };
+
+    light_sampling.bsdf_weight(bsdf_pdf, light_pdf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::Framebuffer;
+    use crate::material::Lambertian;
+    use crate::scheduler::RenderControl;
+    use crate::sphere::Sphere;
+    use crate::test_scenes::{lit_floor, mean};
+
+    fn render(world: &HittableList, lookfrom: Point3, integrator: IntegratorType) -> Framebuffer {
+        render_with_background(world, lookfrom, integrator, Color::new(0.2, 0.3, 0.4))
+    }
+
+    fn render_with_background(
+        world: &HittableList,
+        lookfrom: Point3,
+        integrator: IntegratorType,
+        background: Color,
+    ) -> Framebuffer {
+        Camera::builder()
+            .image_width(4)
+            .samples_per_pixel(256)
+            .vfov(1.)
+            .lookfrom(lookfrom)
+            .lookat(Point3::new(0., 0., 0.))
+            .background(background)
+            .integrator(integrator)
+            .build()
+            .unwrap()
+            .render_with(world, &RenderControl::new())
+    }
+
+    #[test]
+    fn test_first_hit_integrators() {
+        let mut world = HittableList::default();
+        let red = Arc::new(Lambertian::new_from_color(Color::new(0.8, 0.1, 0.1)));
+        world.add(Box::new(Sphere::new(Point3::new(0., 0., 0.), 1., red)));
+        let front = Point3::new(0., 0., 5.);
+
+        let normals = mean(&render(&world, front, IntegratorType::Normals));
+        assert!((normals - Color::new(0.5, 0.5, 1.)).length() < 0.01);
+        let albedo = mean(&render(&world, front, IntegratorType::Albedo));
+        assert!((albedo - Color::new(0.8, 0.1, 0.1)).length() < 1e-12);
+
+        // Nothing is close enough to occlude a lone sphere, while rays that
+        // miss everything show black.
+        let ao = IntegratorType::AmbientOcclusion { radius: 0.5 };
+        assert!(mean(&render(&world, front, ao.clone())) == Color::new(1., 1., 1.));
+        let empty = HittableList::default();
+        assert!(mean(&render(&empty, front, ao)) == Color::default());
+        let background = mean(&render(&empty, front, IntegratorType::Albedo));
+        assert!((background - Color::new(0.2, 0.3, 0.4)).length() < 1e-12);
+    }
+
+    #[test]
+    fn test_direct_integrators_match_path_tracing() {
+        // A floor under a light in the dark, which has no indirect light to
+        // find.
+        let white = Arc::new(Lambertian::new_from_color(Color::new(0.5, 0.5, 0.5)));
+        let world = lit_floor(white, 0.5);
+        let lookfrom = Point3::new(0., 0.5, 3.);
+
+        let black = Color::default();
+        let path = mean(&render_with_background(
+            &world,
+            lookfrom,
+            IntegratorType::Path,
+            black,
+        ))[0];
+        for integrator in [IntegratorType::DirectLighting, IntegratorType::Whitted] {
+            let direct = mean(&render_with_background(&world, lookfrom, integrator, black))[0];
+            assert!((direct - path).abs() < 0.03 * path);
+        }
+    }
+}
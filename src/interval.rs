@@ -1,6 +1,5 @@
 use crate::INFINITY;
-use std::cmp::min;
-use std::ops::{Add, AddAssign, Div, Index, IndexMut, Mul, MulAssign, Neg, Sub};
+use std::ops::{Add, AddAssign};
 
 #[derive(Clone, Copy, Default, PartialEq)]
 pub struct Interval {
@@ -51,12 +50,12 @@ impl Interval {
     }
 }
 
-const EMPTY: Interval = Interval {
+pub const EMPTY: Interval = Interval {
     min: INFINITY,
     max: -INFINITY,
 };
 
-const UNIVERSE: Interval = Interval {
+pub const UNIVERSE: Interval = Interval {
     min: -INFINITY,
     max: INFINITY,
 };
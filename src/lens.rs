@@ -0,0 +1,264 @@
+use crate::vec3::Vec3;
+use crate::{degrees_to_radians, PI};
+use image::ImageResult;
+use std::f64::consts::FRAC_1_SQRT_2;
+use std::path::Path;
+use std::sync::Arc;
+
+/**
+ * The shape of the lens opening, which is also the shape of out of focus
+ * highlights. Every shape is scaled to fit the unit disk.
+ */
+#[derive(Clone, Default)]
+pub enum Aperture {
+    #[default]
+    Disk,
+    /// A regular polygon formed by `blades` aperture blades, rotated by
+    /// `rotation` degrees.
+    Polygon { blades: u32, rotation: f64 },
+    /// An arbitrary shape given by a grayscale mask.
+    Mask(Arc<ApertureMask>),
+}
+
+/**
+ * The lens in front of a perspective camera. The default is an ideal thin
+ * lens with a round aperture.
+ */
+#[derive(Clone, Default)]
+pub struct Lens {
+    pub aperture: Aperture,
+    /// Optical vignetting: how far the lens barrel clips the aperture for
+    /// pixels away from the center, which darkens the corners and gives their
+    /// bokeh a cat-eye shape. Only has an effect with a defocus angle.
+    pub vignetting: f64,
+    /// Radial distortion at the image corners, barrel for positive values and
+    /// pincushion for negative ones.
+    pub distortion: f64,
+    /// Lateral chromatic aberration: how much larger the red channel and how
+    /// much smaller the blue channel is imaged than the green one.
+    pub chromatic_aberration: f64,
+}
+
+impl Lens {
+    /**
+     * Maps a uniform 2D sample to a uniformly distributed point on the
+     * aperture.
+     */
+    pub fn sample_aperture(&self, u: (f64, f64)) -> (f64, f64) {
+        match &self.aperture {
+            Aperture::Disk => {
+                let p = Vec3::sample_in_unit_disk(u);
+                (p[0], p[1])
+            }
+            Aperture::Polygon { blades, rotation } => {
+                // Pick one of the triangles between the center and two
+                // neighbouring corners, then a point within it.
+                let blades = (*blades).max(3) as f64;
+                let scaled = u.0 * blades;
+                let k = scaled.floor().min(blades - 1.);
+                let corner = |k: f64| {
+                    let angle = degrees_to_radians(*rotation) + 2. * PI * k / blades;
+                    (angle.cos(), angle.sin())
+                };
+                let (a, b) = (corner(k), corner(k + 1.));
+
+                let su = (scaled - k).sqrt();
+                (
+                    su * ((1. - u.1) * a.0 + u.1 * b.0),
+                    su * ((1. - u.1) * a.1 + u.1 * b.1),
+                )
+            }
+            Aperture::Mask(mask) => mask.sample(u),
+        }
+    }
+
+    /**
+     * Whether the lens barrel blocks the aperture point `p` for the film
+     * position (x, y), both centered on the image and scaled so the corners
+     * are at a distance of 1.
+     */
+    pub fn is_vignetted(&self, p: (f64, f64), film: (f64, f64)) -> bool {
+        let dx = p.0 - self.vignetting * film.0;
+        let dy = p.1 - self.vignetting * film.1;
+        dx * dx + dy * dy > 1.
+    }
+
+    /**
+     * How much to scale a film position (x, y), centered and scaled like in
+     * `is_vignetted`, to find where the scene is seen through the lens. With
+     * chromatic aberration each `channel` is scaled differently.
+     */
+    pub fn magnification(&self, film: (f64, f64), channel: Option<usize>) -> f64 {
+        let r2 = film.0 * film.0 + film.1 * film.1;
+        let dispersion = match channel {
+            Some(channel) => 1. + self.chromatic_aberration * (1. - channel as f64),
+            None => 1.,
+        };
+
+        (1. + self.distortion * r2) / dispersion
+    }
+
+    pub(crate) fn parameters(&self) -> [f64; 5] {
+        let (blades, rotation) = match self.aperture {
+            Aperture::Disk => (0., 0.),
+            Aperture::Polygon { blades, rotation } => (blades as f64, rotation),
+            Aperture::Mask(ref mask) => (-1., mask.cdf.iter().sum()),
+        };
+
+        [
+            blades,
+            rotation,
+            self.vignetting,
+            self.distortion,
+            self.chromatic_aberration,
+        ]
+    }
+}
+
+/**
+ * An aperture shape from a grayscale image, where brighter pixels let through
+ * more light. The image is scaled so its corners touch the unit circle.
+ * Sampled by inverting the cumulative distribution of its rows and of the
+ * pixels within each row.
+ */
+#[derive(Clone, Debug)]
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    row_cdf: Vec<f64>,
+    cdf: Vec<f64>,
+}
+
+impl ApertureMask {
+    pub fn new(width: usize, height: usize, values: &[f64]) -> Self {
+        assert_eq!(values.len(), width * height);
+
+        let mut cdf = Vec::with_capacity(width * height);
+        let mut row_cdf = Vec::with_capacity(height);
+        let mut total = 0.;
+        for row in values.chunks(width) {
+            let mut sum = 0.;
+            for &value in row {
+                sum += value.max(0.);
+                cdf.push(sum);
+            }
+            total += sum;
+            row_cdf.push(total);
+        }
+        assert!(total > 0., "aperture mask is black");
+
+        Self {
+            width,
+            height,
+            row_cdf,
+            cdf,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> ImageResult<Self> {
+        let image = image::open(path)?.to_luma32f();
+        let values: Vec<f64> = image.pixels().map(|p| p.0[0] as f64).collect();
+
+        Ok(Self::new(
+            image.width() as usize,
+            image.height() as usize,
+            &values,
+        ))
+    }
+
+    fn sample(&self, u: (f64, f64)) -> (f64, f64) {
+        let (y, v) = sample_cdf(&self.row_cdf, u.1);
+        let (x, u) = sample_cdf(&self.cdf[y * self.width..(y + 1) * self.width], u.0);
+
+        (
+            FRAC_1_SQRT_2 * (2. * (x as f64 + u) / self.width as f64 - 1.),
+            FRAC_1_SQRT_2 * (1. - 2. * (y as f64 + v) / self.height as f64),
+        )
+    }
+}
+
+/**
+ * Finds the bin of a running sum `cdf` that `u` falls into, along with the
+ * position of `u` within it, rescaled to [0, 1).
+ */
+fn sample_cdf(cdf: &[f64], u: f64) -> (usize, f64) {
+    let total = cdf[cdf.len() - 1];
+    let target = u * total;
+    let k = cdf.partition_point(|&c| c <= target).min(cdf.len() - 1);
+
+    let start = if k == 0 { 0. } else { cdf[k - 1] };
+    let width = cdf[k] - start;
+    let offset = if width > 0. {
+        ((target - start) / width).clamp(0., 1.)
+    } else {
+        0.5
+    };
+
+    (k, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(n: usize) -> impl Iterator<Item = (f64, f64)> {
+        (0..n * n).map(move |k| {
+            (
+                ((k % n) as f64 + 0.5) / n as f64,
+                ((k / n) as f64 + 0.5) / n as f64,
+            )
+        })
+    }
+
+    #[test]
+    fn test_polygon_stays_inside() {
+        let lens = Lens {
+            aperture: Aperture::Polygon {
+                blades: 6,
+                rotation: 0.,
+            },
+            ..Default::default()
+        };
+
+        // The inner radius of a hexagon is cos(30°) of the outer one.
+        let inner = (PI / 6.).cos();
+        let mut reaches_corner = false;
+        for u in grid(64) {
+            let (x, y) = lens.sample_aperture(u);
+            let r = x.hypot(y);
+            assert!(r <= 1. + 1e-9);
+
+            let angle = y.atan2(x).rem_euclid(PI / 3.) - PI / 6.;
+            assert!(r * angle.cos() <= inner + 1e-9);
+            reaches_corner |= r > 0.95;
+        }
+        assert!(reaches_corner);
+    }
+
+    #[test]
+    fn test_mask_follows_image() {
+        // Only the top-right quarter is open.
+        let mask = ApertureMask::new(2, 2, &[0., 1., 0., 0.]);
+        for u in grid(16) {
+            let (x, y) = mask.sample(u);
+            assert!((0. ..=1.).contains(&x) && (0. ..=1.).contains(&y));
+            assert!(x.hypot(y) <= 1. + 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_vignetting_and_distortion() {
+        let lens = Lens {
+            vignetting: 0.5,
+            distortion: 0.1,
+            chromatic_aberration: 0.01,
+            ..Default::default()
+        };
+
+        assert!(!lens.is_vignetted((-0.9, 0.), (0., 0.)));
+        assert!(lens.is_vignetted((-0.9, 0.), (0.8, 0.)));
+        assert_eq!(lens.magnification((0., 0.), None), 1.);
+        assert_eq!(lens.magnification((0.6, 0.8), None), 1.1);
+        assert!(lens.magnification((0.6, 0.8), Some(0)) < lens.magnification((0.6, 0.8), Some(2)));
+    }
+}
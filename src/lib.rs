@@ -1,4 +1,44 @@
-use rand::Rng;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+
+pub mod aabb;
+pub mod adaptive;
+pub mod animation;
+pub mod aov;
+pub mod bvh;
+pub mod camera;
+pub mod color;
+pub mod denoise;
+pub mod film;
+pub mod filter;
+pub mod framebuffer;
+pub mod heatmap;
+pub mod hittable;
+pub mod hittable_list;
+pub mod integrator;
+pub mod interval;
+pub mod lens;
+pub mod material;
+pub mod mis;
+pub mod motion;
+pub mod onb;
+pub mod output;
+pub mod pdf;
+pub mod perlin;
+pub mod progressive;
+pub mod projection;
+pub mod quad;
+pub mod ray;
+pub mod sampler;
+pub mod scheduler;
+pub mod sphere;
+pub mod stats;
+#[cfg(test)]
+mod test_scenes;
+pub mod texture;
+pub mod tonemap;
+pub mod vec3;
 
 pub const INFINITY: f64 = f64::INFINITY;
 pub const PI: f64 = std::f64::consts::PI;
@@ -7,16 +47,37 @@ pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.
 }
 
+thread_local! {
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_entropy());
+}
+
+/**
+ * Reseeds the random number generator of the calling thread, so that every
+ * following draw on this thread is reproducible.
+ */
+pub fn seed_random(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(seed));
+}
+
+/**
+ * Derives an independent seed for `stream` from `seed`, using the SplitMix64
+ * finalizer so neighbouring streams end up uncorrelated.
+ */
+pub fn mix_seed(seed: u64, stream: u64) -> u64 {
+    let mut z = seed ^ stream.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
 pub fn random_int(min: i32, max: i32) -> i32 {
     random_double_r(min as f64, (max + 1) as f64).round() as i32
 }
 
 pub fn random_double() -> f64 {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(0f64..1.)
+    RNG.with(|rng| rng.borrow_mut().gen_range(0f64..1.))
 }
 
 pub fn random_double_r(min: f64, max: f64) -> f64 {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(min..max)
+    RNG.with(|rng| rng.borrow_mut().gen_range(min..max))
 }
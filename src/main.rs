@@ -1,37 +1,35 @@
-use crate::bvh::*;
-use crate::camera::Camera;
-use crate::color::Color;
-use crate::hittable::*;
-use crate::hittable_list::HittableList;
-use crate::material::{Dielectric, Lambertian, Material, Metal};
-use crate::quad::*;
-use crate::sphere::Sphere;
-use crate::texture::*;
-use crate::vec3::{Point3, Vec3};
-use material::DiffuseLight;
-use ray_tracing::{random_double, random_double_r, INFINITY, PI};
+use ray_tracing::adaptive::AdaptiveSampling;
+use ray_tracing::animation::{Animation, Interpolation, Track};
+use ray_tracing::bvh::*;
+use ray_tracing::camera::Camera;
+use ray_tracing::color::Color;
+use ray_tracing::filter::MitchellFilter;
+use ray_tracing::framebuffer::Framebuffer;
+use ray_tracing::hittable::*;
+use ray_tracing::hittable_list::HittableList;
+use ray_tracing::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use ray_tracing::motion::{Shutter, ShutterCurve};
+use ray_tracing::output::{save, write_ppm};
+use ray_tracing::progressive::ProgressiveSettings;
+use ray_tracing::quad::*;
+use ray_tracing::sampler::SamplerType;
+use ray_tracing::scheduler::RenderControl;
+use ray_tracing::sphere::Sphere;
+use ray_tracing::stats::{self, Phase};
+use ray_tracing::texture::*;
+use ray_tracing::tonemap::Aces;
+use ray_tracing::vec3::{Point3, Vec3};
+use ray_tracing::{random_double, random_double_r, seed_random};
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Instant;
 
 // Based on the book "Ray Tracing in One Weekend": https://raytracing.github.io/books/RayTracingInOneWeekend.html
 // and written in Rust
 
-mod aabb;
-mod bvh;
-mod camera;
-mod color;
-mod hittable;
-mod hittable_list;
-mod interval;
-mod material;
-mod perlin;
-mod quad;
-mod ray;
-mod sphere;
-mod texture;
-mod vec3;
-
-fn random_spheres() {
+fn random_spheres() -> Framebuffer {
     // Image
     let aspect_ratio = 16. / 9.;
     let image_width = 1200;
@@ -109,21 +107,26 @@ fn random_spheres() {
     )));
 
     world = HittableList::new(BvhNode::new(world));
-    let mut camera = Camera::new(aspect_ratio, image_width, samples_per_pixel, max_depth);
+    let camera = Camera::builder()
+        .aspect_ratio(aspect_ratio)
+        .image_width(image_width)
+        .samples_per_pixel(samples_per_pixel)
+        .max_depth(max_depth)
+        .vfov(20.)
+        .lookfrom(Point3::new(13., 2., 3.))
+        .lookat(Point3::new(0., 0., 0.))
+        .vup(Vec3::new(0., 1., 0.))
+        .defocus_angle(0.6)
+        .focus_dist(10.)
+        .background(Color::new(0.7, 0.8, 1.))
+        .filter(Arc::new(MitchellFilter::default()))
+        .build()
+        .expect("invalid camera");
 
-    camera.vfov = 20.;
-    camera.lookfrom = Point3::new(13., 2., 3.);
-    camera.lookat = Point3::new(0., 0., 0.);
-    camera.vup = Vec3::new(0., 1., 0.);
-
-    camera.defocus_angle = 0.6;
-    camera.focus_dist = 10.;
-    camera.background = Color::new(0.7, 0.8, 1.);
-
-    camera.render(&world);
+    camera.render(&world)
 }
 
-fn test() {
+fn test() -> Framebuffer {
     let mut world = HittableList::default();
 
     let material_ground = Lambertian::new_from_color(Color::new(0.8, 0.8, 0.));
@@ -160,17 +163,23 @@ fn test() {
         Arc::new(material_right),
     )));
 
-    let mut camera = Camera::new(16. / 9., 400, 100, 50);
-    camera.vfov = 90.;
-    camera.lookfrom = Point3::new(-2., 2., 1.);
-    camera.lookat = Point3::new(0., 0., -1.);
-    camera.vup = Vec3::new(0., 1., 0.);
-    camera.background = Color::new(0.7, 0.8, 1.);
+    let camera = Camera::builder()
+        .aspect_ratio(16. / 9.)
+        .image_width(400)
+        .samples_per_pixel(100)
+        .max_depth(50)
+        .vfov(90.)
+        .lookfrom(Point3::new(-2., 2., 1.))
+        .lookat(Point3::new(0., 0., -1.))
+        .vup(Vec3::new(0., 1., 0.))
+        .background(Color::new(0.7, 0.8, 1.))
+        .build()
+        .expect("invalid camera");
 
-    camera.render(&world);
+    camera.render(&world)
 }
 
-fn two_spheres() {
+fn two_spheres() -> Framebuffer {
     let mut world = HittableList::default();
 
     let checker =
@@ -191,44 +200,52 @@ fn two_spheres() {
 
     // world = HittableList::new(BvhNode::new(world));
 
-    let mut camera = Camera::new(16. / 9., 600, 100, 50);
-
-    camera.vfov = 20.;
-    camera.lookfrom = Point3::new(13., 2., 3.);
-    camera.lookat = Point3::new(0., 0., 0.);
-    camera.vup = Vec3::new(0., 1., 0.);
-    camera.background = Color::new(0.7, 0.8, 1.);
-
-    camera.defocus_angle = 0.;
-    camera.focus_dist = 10.;
+    let camera = Camera::builder()
+        .aspect_ratio(16. / 9.)
+        .image_width(600)
+        .samples_per_pixel(100)
+        .max_depth(50)
+        .vfov(20.)
+        .lookfrom(Point3::new(13., 2., 3.))
+        .lookat(Point3::new(0., 0., 0.))
+        .vup(Vec3::new(0., 1., 0.))
+        .background(Color::new(0.7, 0.8, 1.))
+        .defocus_angle(0.)
+        .focus_dist(10.)
+        .build()
+        .expect("invalid camera");
 
-    camera.render(&world);
+    camera.render(&world)
 }
 
-fn test2() {
+fn test2() -> Framebuffer {
     let material = Lambertian::new_from_color(Color::new(0., 155., 70.));
     let sphere = Box::new(Sphere::new(Point3::new(0., 0., 0.), 2., Arc::new(material)));
 
     let mut world = HittableList::default();
     world.add(sphere);
 
-    let mut camera = Camera::new(16. / 9., 1080, 400, 50);
-
-    camera.vfov = 20.;
-    camera.lookfrom = Point3::new(0., 0., 12.);
-    camera.lookat = Point3::new(0., 0., 0.);
-    camera.vup = Vec3::new(0., 1., 0.);
-    camera.background = Color::new(0.7, 0.8, 1.);
-
-    camera.defocus_angle = 0.;
+    let camera = Camera::builder()
+        .aspect_ratio(16. / 9.)
+        .image_width(1080)
+        .samples_per_pixel(400)
+        .max_depth(50)
+        .vfov(20.)
+        .lookfrom(Point3::new(0., 0., 12.))
+        .lookat(Point3::new(0., 0., 0.))
+        .vup(Vec3::new(0., 1., 0.))
+        .background(Color::new(0.7, 0.8, 1.))
+        .defocus_angle(0.)
+        .build()
+        .expect("invalid camera");
 
-    camera.render(&world);
+    camera.render(&world)
 }
 
-fn earth() {
+fn earth() -> Framebuffer {
     let load_texture = ImageTexture::new("earthmap.jpg");
     match load_texture {
-        Err(_err) => (),
+        Err(_err) => Framebuffer::default(),
         Ok(earth_texture) => {
             let earth_surface = Lambertian::new(earth_texture);
             let globe_mat: Arc<dyn Material + Send> = Arc::new(earth_surface);
@@ -237,22 +254,26 @@ fn earth() {
             let mut world = HittableList::default();
             world.add(Box::new(globe));
 
-            let mut camera = Camera::new(16. / 9., 1080, 400, 50);
-
-            camera.vfov = 20.;
-            camera.lookfrom = Point3::new(0., 0., 12.);
-            camera.lookat = Point3::new(0., 0., 0.);
-            camera.vup = Vec3::new(0., 1., 0.);
-            camera.background = Color::new(0.7, 0.8, 1.);
-
-            camera.defocus_angle = 0.;
-
-            camera.render(&world);
+            let camera = Camera::builder()
+                .aspect_ratio(16. / 9.)
+                .image_width(1080)
+                .samples_per_pixel(400)
+                .max_depth(50)
+                .vfov(20.)
+                .lookfrom(Point3::new(0., 0., 12.))
+                .lookat(Point3::new(0., 0., 0.))
+                .vup(Vec3::new(0., 1., 0.))
+                .background(Color::new(0.7, 0.8, 1.))
+                .defocus_angle(0.)
+                .build()
+                .expect("invalid camera");
+
+            camera.render(&world)
         }
     }
 }
 
-fn two_perlin_spheres() {
+fn two_perlin_spheres() -> Framebuffer {
     let mut world = HittableList::default();
 
     let pertext = NoiseTexture::new(4.);
@@ -267,19 +288,23 @@ fn two_perlin_spheres() {
         Arc::new(Lambertian::new(pertext)),
     )));
 
-    let mut camera = Camera::new(16. / 9., 400, 100, 50);
-
-    camera.vfov = 20.;
-    camera.lookfrom = Point3::new(13., 2., 3.);
-    camera.lookat = Point3::new(0., 0., 0.);
-    camera.vup = Vec3::new(0., 1., 0.);
-    camera.background = Color::new(0.7, 0.8, 1.);
-
-    camera.defocus_angle = 0.;
+    let camera = Camera::builder()
+        .aspect_ratio(16. / 9.)
+        .image_width(400)
+        .samples_per_pixel(100)
+        .max_depth(50)
+        .vfov(20.)
+        .lookfrom(Point3::new(13., 2., 3.))
+        .lookat(Point3::new(0., 0., 0.))
+        .vup(Vec3::new(0., 1., 0.))
+        .background(Color::new(0.7, 0.8, 1.))
+        .defocus_angle(0.)
+        .build()
+        .expect("invalid camera");
     camera.render(&world)
 }
 
-fn quads() {
+fn quads() -> Framebuffer {
     let mut world = HittableList::default();
 
     let left_red = Arc::new(Lambertian::new_from_color(Color::new(1., 0.2, 0.2)));
@@ -319,20 +344,24 @@ fn quads() {
         lower_teal,
     )));
 
-    let mut cam = Camera::new(1., 800, 500, 50);
-
-    cam.vfov = 80.;
-    cam.lookfrom = Point3::new(0., 0., 9.);
-    cam.lookat = Point3::new(0., 0., 0.);
-    cam.vup = Vec3::new(0., 1., 0.);
-    cam.background = Color::new(0.7, 0.8, 1.);
-
-    cam.defocus_angle = 0.;
-
-    cam.render(&world);
+    let cam = Camera::builder()
+        .aspect_ratio(1.)
+        .image_width(800)
+        .samples_per_pixel(500)
+        .max_depth(50)
+        .vfov(80.)
+        .lookfrom(Point3::new(0., 0., 9.))
+        .lookat(Point3::new(0., 0., 0.))
+        .vup(Vec3::new(0., 1., 0.))
+        .background(Color::new(0.7, 0.8, 1.))
+        .defocus_angle(0.)
+        .build()
+        .expect("invalid camera");
+
+    cam.render(&world)
 }
 
-fn simple_light() {
+fn simple_light() -> Framebuffer {
     let mut world = HittableList::default();
 
     let pertext = Arc::new(Lambertian::new(NoiseTexture::new(4.)));
@@ -362,24 +391,25 @@ fn simple_light() {
 
     world = HittableList::new(BvhNode::new(world));
 
-    let mut cam = Camera::new(16. / 9., 1600, 1500, 50);
-
-    cam.background = Color::new(0., 0., 0.);
-    cam.vfov = 20.;
-    cam.lookfrom = Point3::new(26., 3., 6.);
-    cam.lookat = Point3::new(0., 2., 0.);
-    cam.vup = Vec3::new(0., 1., 0.);
-
-    cam.defocus_angle = 0.;
-
-    cam.render(&world);
+    let cam = Camera::builder()
+        .aspect_ratio(16. / 9.)
+        .image_width(1600)
+        .samples_per_pixel(1500)
+        .max_depth(50)
+        .background(Color::new(0., 0., 0.))
+        .vfov(20.)
+        .lookfrom(Point3::new(26., 3., 6.))
+        .lookat(Point3::new(0., 2., 0.))
+        .vup(Vec3::new(0., 1., 0.))
+        .defocus_angle(0.)
+        .build()
+        .expect("invalid camera");
+
+    cam.render(&world)
 }
 
-fn cornell_box() {
-    let mut world = HittableList::default();
-
+fn cornell_walls(world: &mut HittableList, white: Arc<dyn Material + Send>) {
     let red = Arc::new(Lambertian::new_from_color(Color::new(0.65, 0.05, 0.05)));
-    let white = Arc::new(Lambertian::new_from_color(Color::new(0.73, 0.73, 0.73)));
     let green = Arc::new(Lambertian::new_from_color(Color::new(0.12, 0.45, 0.15)));
     let light = Arc::new(DiffuseLight::new_with_color(Color::new(15., 15., 15.)));
 
@@ -417,8 +447,15 @@ fn cornell_box() {
         Point3::new(0., 0., 555.),
         Vec3::new(555., 0., 0.),
         Vec3::new(0., 555., 0.),
-        white.clone(),
+        white,
     )));
+}
+
+fn cornell_box() -> Framebuffer {
+    let mut world = HittableList::default();
+
+    let white = Arc::new(Lambertian::new_from_color(Color::new(0.73, 0.73, 0.73)));
+    cornell_walls(&mut world, white.clone());
 
     let mut box1 = r#box(
         Point3::new(0., 0., 0.),
@@ -440,22 +477,166 @@ fn cornell_box() {
 
     world = HittableList::new(BvhNode::new(world));
 
-    let mut cam = Camera::new(1., 600, 5000, 50);
-    cam.background = Color::default();
+    let cam = Camera::builder()
+        .aspect_ratio(1.)
+        .image_width(600)
+        .samples_per_pixel(1000)
+        .max_depth(50)
+        .russian_roulette(Some(3))
+        .background(Color::default())
+        .tone_mapper(Arc::new(Aces))
+        .sampler(SamplerType::Sobol)
+        .adaptive(AdaptiveSampling::new(64, 1000, 0.01))
+        .aovs(true)
+        .vfov(40.)
+        .lookfrom(Point3::new(278., 278., -800.))
+        .lookat(Point3::new(278., 278., 0.))
+        .vup(Vec3::new(0., 1., 0.))
+        .defocus_angle(0.)
+        .build()
+        .expect("invalid camera");
+
+    // Long render, so keep a checkpoint to resume from if it gets interrupted.
+    // Once it finishes the checkpoint is removed, so the next run renders the
+    // scene again instead of returning the old image.
+    let checkpoint = PathBuf::from("cornell_box.checkpoint");
+    let settings = ProgressiveSettings {
+        pass_samples: 64,
+        checkpoint: Some(checkpoint.clone()),
+        resume: true,
+        ..Default::default()
+    };
+    let control = RenderControl::stderr();
+    let image = cam
+        .render_progressive(&world, &settings, &control)
+        .expect("failed to checkpoint render");
+    if !control.cancellation.is_cancelled() {
+        fs::remove_file(&checkpoint).expect("failed to remove checkpoint");
+    }
+    eprintln!("\rDone.                  \n");
+
+    image
+}
 
-    cam.vfov = 40.;
-    cam.lookfrom = Point3::new(278., 278., -800.);
-    cam.lookat = Point3::new(278., 278., 0.);
-    cam.vup = Vec3::new(0., 1., 0.);
+/**
+ * Two seconds of the Cornell box: the camera pushes in while the short box
+ * spins and the tall one fades from white to red. The walls are static and
+ * share one BVH across all frames.
+ */
+fn cornell_box_animation() -> Animation {
+    let mut walls = HittableList::default();
+    let white = Arc::new(Lambertian::new_from_color(Color::new(0.73, 0.73, 0.73)));
+    cornell_walls(&mut walls, white.clone());
+
+    let spin =
+        Track::new()
+            .key(0., -18., Interpolation::Bezier)
+            .key(2., 162., Interpolation::Bezier);
+    let tall_color = Track::new()
+        .key(0., Color::new(0.73, 0.73, 0.73), Interpolation::Linear)
+        .key(2., Color::new(0.65, 0.05, 0.05), Interpolation::Linear);
+
+    let cam = Camera::builder()
+        .aspect_ratio(1.)
+        .image_width(300)
+        .samples_per_pixel(64)
+        .max_depth(50)
+        .background(Color::default())
+        .tone_mapper(Arc::new(Aces))
+        .sampler(SamplerType::Sobol)
+        .vfov(40.)
+        .lookfrom(Point3::new(278., 278., -800.))
+        .lookat(Point3::new(278., 278., 0.))
+        .vup(Vec3::new(0., 1., 0.))
+        .shutter(Shutter::new(0., 0.5, ShutterCurve::Box))
+        .build()
+        .expect("invalid camera");
+
+    let mut animation = Animation::new(cam, walls, move |time| {
+        let mut world = HittableList::default();
+
+        let tall_material = Arc::new(Lambertian::new_from_color(tall_color.sample(time)));
+        let mut box1 = r#box(
+            Point3::new(0., 0., 0.),
+            Point3::new(165., 330., 165.),
+            tall_material,
+        );
+        box1 = Box::new(RotateY::new(box1, 15.));
+        box1 = Box::new(Translate::new(box1, Vec3::new(265., 0., 295.)));
+        world.add(box1);
+
+        let mut box2 = r#box(
+            Point3::new(-82.5, 0., -82.5),
+            Point3::new(82.5, 165., 82.5),
+            white.clone(),
+        );
+        box2 = Box::new(RotateY::new(box2, spin.sample(time)));
+        box2 = Box::new(Translate::new(box2, Vec3::new(212.5, 0., 147.5)));
+        world.add(box2);
+
+        world
+    });
+    animation.camera_tracks.lookfrom = Some(
+        Track::new()
+            .key(0., Point3::new(278., 278., -800.), Interpolation::Bezier)
+            .key(2., Point3::new(278., 278., -500.), Interpolation::Bezier),
+    );
 
-    cam.defocus_angle = 0.;
+    animation
+}
 
-    cam.render(&world);
+/**
+ * Prints the statistics collected during the run, as a table or with
+ * `--stats=json` as JSON.
+ */
+fn report_stats(flag: &str) {
+    let stats = stats::snapshot();
+    if flag == "--stats=json" {
+        println!("{}", stats.to_json());
+    } else {
+        eprint!("{}", stats);
+    }
 }
 
 fn main() {
     let before = Instant::now();
-    match 7 {
+
+    // Seed the scene construction too, so the same scene is built every run.
+    seed_random(0);
+
+    // `--stats` anywhere on the command line reports where the work went.
+    let mut args: Vec<String> = std::env::args().collect();
+    let stats_flag = args
+        .iter()
+        .position(|arg| arg.starts_with("--stats"))
+        .map(|k| args.remove(k));
+    if stats_flag.is_some() {
+        stats::enable();
+    }
+
+    // `--frames 0-47 frames/cornell_####.png` renders an animation instead.
+    if args.get(1).map(String::as_str) == Some("--frames") {
+        let usage = "usage: --frames FIRST-LAST PATTERN";
+        let (first, last) = args
+            .get(2)
+            .and_then(|range| range.split_once('-'))
+            .and_then(|(first, last)| Some((first.parse().ok()?, last.parse().ok()?)))
+            .expect(usage);
+        let pattern = args.get(3).expect(usage);
+
+        stats::time(Phase::SceneBuild, cornell_box_animation)
+            .render_frames(first..=last, pattern, &RenderControl::stderr())
+            .expect("failed to save frames");
+        eprintln!("\rDone.                  \n");
+        if let Some(flag) = &stats_flag {
+            report_stats(flag);
+        }
+        eprintln!("Elapsed time: {:.2?}", before.elapsed());
+        return;
+    }
+
+    // The scenes render themselves, which is timed on its own.
+    let image = stats::time(Phase::SceneBuild, || match 7 {
         1 => random_spheres(),
         2 => two_spheres(),
         3 => earth(),
@@ -463,7 +644,30 @@ fn main() {
         5 => quads(),
         6 => simple_light(),
         7 => cornell_box(),
+        8 => test2(),
         _ => test(),
+    });
+
+    stats::time(Phase::Output, || {
+        // Write to the file given on the command line, or PPM to stdout by default.
+        match args.get(1) {
+            Some(path) => save(path, &image).expect("failed to save image"),
+            None => {
+                let mut out = BufWriter::new(io::stdout().lock());
+                write_ppm(&mut out, &image)
+                    .and_then(|_| out.flush())
+                    .expect("failed to write image");
+            }
+        }
+
+        // An optional second path receives the adaptive sample count map.
+        if let (Some(path), Some(counts)) = (args.get(2), image.sample_count_image()) {
+            save(path, &counts).expect("failed to save sample counts");
+        }
+    });
+
+    if let Some(flag) = &stats_flag {
+        report_stats(flag);
     }
     eprintln!("Elapsed time: {:.2?}", before.elapsed());
 }
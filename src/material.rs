@@ -1,22 +1,76 @@
 use crate::color::Color;
 use crate::hittable::HitRecord;
+use crate::pdf::{CosinePdf, FuzzPdf, Pdf};
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::texture::{SolidColor, Texture};
 use crate::vec3::{Point3, Vec3};
-use ray_tracing::random_double;
+use crate::PI;
+
+/**
+ * How light leaves a surface after a hit.
+ */
+pub enum Lobe {
+    /// Along a single direction, followed as it is.
+    Specular(Ray),
+    /// Over the directions of a density, for diffuse and glossy surfaces,
+    /// which the integrator samples and weights by `Material::scattering_pdf`.
+    Diffuse(Box<dyn Pdf>),
+}
+
+pub struct ScatterRecord {
+    pub attenuation: Color,
+    pub lobe: Lobe,
+}
+
+impl Default for ScatterRecord {
+    fn default() -> Self {
+        Self {
+            attenuation: Color::default(),
+            lobe: Lobe::Specular(Ray::default()),
+        }
+    }
+}
 
 pub trait Material: Sync + Send {
+    /**
+     * Fills in how the surface scatters the ray `r_in` at the hit, or
+     * returns false if it absorbs it.
+     */
     fn scatter(
         &self,
         r_in: &Ray,
         rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn Sampler,
     ) -> bool;
 
+    /**
+     * The density with which the surface scatters `r_in` into `scattered`,
+     * for diffuse lobes.
+     */
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        0.
+    }
+
     fn emitted(&self, _u: f64, _v: f64, _p: Point3) -> Color {
         Color::default()
     }
+
+    /**
+     * The base color of the surface at the hit, written to the albedo AOV.
+     */
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::default()
+    }
+
+    /**
+     * Whether objects made of the material are lights, which get sampled
+     * directly.
+     */
+    fn is_emissive(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Clone, Copy, Default)]
@@ -41,21 +95,23 @@ impl Lambertian<SolidColor> {
 impl<T: Texture> Material for Lambertian<T> {
     fn scatter(
         &self,
-        r_in: &Ray,
+        _r_in: &Ray,
         rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
+        srec: &mut ScatterRecord,
+        _sampler: &mut dyn Sampler,
     ) -> bool {
-        let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
+        srec.attenuation = self.albedo.value(rec.u, rec.v, rec.p);
+        srec.lobe = Lobe::Diffuse(Box::new(CosinePdf::new(rec.normal)));
+        true
+    }
 
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal;
-            eprintln!("NEAR ZERO")
-        }
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cos_theta = rec.normal.dot(scattered.direction().unit_vector());
+        (cos_theta / PI).max(0.)
+    }
 
-        *scattered = Ray::new_with_time(rec.p, scatter_direction, r_in.time());
-        *attenuation = self.albedo.value(rec.u, rec.v, rec.p);
-        true
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.value(rec.u, rec.v, rec.p)
     }
 }
 
@@ -69,6 +125,19 @@ impl Metal {
     pub fn new(a: Color, f: f64) -> Self {
         Self { albedo: a, fuzz: f }
     }
+
+    /**
+     * The mirror direction moved to a random point within `fuzz` of it, or
+     * `None` for a perfect mirror.
+     */
+    fn lobe(&self, r_in: &Ray, rec: &HitRecord) -> Option<FuzzPdf> {
+        if self.fuzz <= 0. {
+            return None;
+        }
+        let reflected = Vec3::reflect(r_in.direction().unit_vector(), rec.normal);
+
+        Some(FuzzPdf::new(reflected, self.fuzz))
+    }
 }
 
 impl Default for Metal {
@@ -85,18 +154,30 @@ impl Material for Metal {
         &self,
         r_in: &Ray,
         rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
+        srec: &mut ScatterRecord,
+        _sampler: &mut dyn Sampler,
     ) -> bool {
-        let reflected = Vec3::reflect(r_in.direction().unit_vector(), rec.normal);
-        *scattered = Ray::new_with_time(
-            rec.p,
-            reflected + self.fuzz * Vec3::random_in_unit_sphere(),
-            r_in.time(),
-        );
-        *attenuation = self.albedo;
+        srec.attenuation = self.albedo;
+        srec.lobe = match self.lobe(r_in, rec) {
+            Some(lobe) => Lobe::Diffuse(Box::new(lobe)),
+            None => {
+                let reflected = Vec3::reflect(r_in.direction().unit_vector(), rec.normal);
+                Lobe::Specular(Ray::new_with_time(rec.p, reflected, r_in.time()))
+            }
+        };
         true
     }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        match self.lobe(r_in, rec) {
+            Some(lobe) => lobe.value(scattered.direction()),
+            None => 0.,
+        }
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -132,10 +213,10 @@ impl Material for Dielectric {
         &self,
         r_in: &Ray,
         rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn Sampler,
     ) -> bool {
-        *attenuation = Color::new(1.0, 1.0, 1.0);
+        srec.attenuation = Color::new(1.0, 1.0, 1.0);
         let refraction_ratio = if rec.front_face {
             1. / self.ir
         } else {
@@ -147,17 +228,22 @@ impl Material for Dielectric {
         let sin_theta = (1. - cos_theta * cos_theta).sqrt();
 
         let cannot_refract = refraction_ratio * sin_theta > 1.;
+        let reflect_sample = sampler.get_1d();
         let direction = if cannot_refract
-            || Dielectric::reflectance(cos_theta, refraction_ratio) > random_double()
+            || Dielectric::reflectance(cos_theta, refraction_ratio) > reflect_sample
         {
             Vec3::reflect(unit_direction, rec.normal)
         } else {
             Vec3::refract(unit_direction, rec.normal, refraction_ratio)
         };
 
-        *scattered = Ray::new_with_time(rec.p, direction, r_in.time());
+        srec.lobe = Lobe::Specular(Ray::new_with_time(rec.p, direction, r_in.time()));
         true
     }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::new(1., 1., 1.)
+    }
 }
 
 pub struct DiffuseLight<T: Texture> {
@@ -183,8 +269,8 @@ impl<T: Texture> Material for DiffuseLight<T> {
         &self,
         _r_in: &Ray,
         _rec: &HitRecord,
-        _attenuation: &mut Color,
-        _scattered: &mut Ray,
+        _srec: &mut ScatterRecord,
+        _sampler: &mut dyn Sampler,
     ) -> bool {
         false
     }
@@ -192,4 +278,13 @@ impl<T: Texture> Material for DiffuseLight<T> {
     fn emitted(&self, u: f64, v: f64, p: Point3) -> Color {
         self.emit.value(u, v, p)
     }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        let emitted = self.emitted(rec.u, rec.v, rec.p);
+        Color::new(emitted[0].min(1.), emitted[1].min(1.), emitted[2].min(1.))
+    }
 }
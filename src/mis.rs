@@ -0,0 +1,104 @@
+/**
+ * How the direct light at diffuse and glossy hits is estimated.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightSampling {
+    /// Only by following the scattered ray, hoping it reaches a light.
+    Bsdf,
+    /// Only by sampling points on the lights of the scene.
+    Lights,
+    /// By both, combined with multiple importance sampling.
+    Mis(Heuristic),
+}
+
+impl Default for LightSampling {
+    fn default() -> Self {
+        LightSampling::Mis(Heuristic::Power)
+    }
+}
+
+impl LightSampling {
+    /**
+     * The weight of a light sample drawn with density `light_pdf` that the
+     * scattering would have drawn with density `bsdf_pdf`.
+     */
+    pub(crate) fn light_weight(&self, light_pdf: f64, bsdf_pdf: f64) -> f64 {
+        match self {
+            LightSampling::Bsdf => 0.,
+            LightSampling::Lights => 1.,
+            LightSampling::Mis(heuristic) => heuristic.weight(light_pdf, bsdf_pdf),
+        }
+    }
+
+    /**
+     * The weight of the emission found by a scattered ray drawn with density
+     * `bsdf_pdf`, which light sampling would have found with density
+     * `light_pdf`.
+     */
+    pub(crate) fn bsdf_weight(&self, bsdf_pdf: f64, light_pdf: f64) -> f64 {
+        match self {
+            LightSampling::Bsdf => 1.,
+            LightSampling::Lights if light_pdf > 0. => 0.,
+            LightSampling::Lights => 1.,
+            LightSampling::Mis(heuristic) => heuristic.weight(bsdf_pdf, light_pdf),
+        }
+    }
+}
+
+/**
+ * How multiple importance sampling weighs a sample against the other
+ * strategy, given the densities both would have drawn it with.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Heuristic {
+    /// Proportional to the densities.
+    Balance,
+    /// Proportional to the squared densities, which favors the strategy that
+    /// is much better at a sample.
+    #[default]
+    Power,
+}
+
+impl Heuristic {
+    /**
+     * The weight of a sample drawn with density `pdf` when the other strategy
+     * has density `other_pdf` for it.
+     */
+    pub fn weight(&self, pdf: f64, other_pdf: f64) -> f64 {
+        let (a, b) = match self {
+            Heuristic::Balance => (pdf, other_pdf),
+            Heuristic::Power => (pdf * pdf, other_pdf * other_pdf),
+        };
+        if a + b > 0. {
+            a / (a + b)
+        } else {
+            0.
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_weights_sum_to_one() {
+        for heuristic in [Heuristic::Balance, Heuristic::Power] {
+            for (p, q) in [(1., 1.), (0.2, 3.), (5., 0.), (0., 0.5)] {
+                let sum = heuristic.weight(p, q) + heuristic.weight(q, p);
+                assert!((sum - 1.).abs() < 1e-12);
+            }
+        }
+        assert_eq!(Heuristic::Balance.weight(1., 3.), 0.25);
+        assert_eq!(Heuristic::Power.weight(1., 3.), 0.1);
+
+        for sampling in [
+            LightSampling::Bsdf,
+            LightSampling::Lights,
+            LightSampling::Mis(Heuristic::Power),
+        ] {
+            let sum = sampling.light_weight(2., 0.5) + sampling.bsdf_weight(0.5, 2.);
+            assert!((sum - 1.).abs() < 1e-12);
+        }
+    }
+}
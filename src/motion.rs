@@ -0,0 +1,166 @@
+use crate::vec3::Point3;
+
+/**
+ * How far the shutter is open over time, which weights the moments within
+ * the exposure.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ShutterCurve {
+    /// Opens and closes instantly.
+    #[default]
+    Box,
+    /// Opens and closes linearly, each taking the fraction `ramp` of the
+    /// exposure, at most 0.5.
+    Trapezoid { ramp: f64 },
+}
+
+/**
+ * The interval of scene time the camera is exposed for. Moving spheres move
+ * from their first to their second center over the times 0 to 1.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shutter {
+    pub open: f64,
+    pub close: f64,
+    pub curve: ShutterCurve,
+}
+
+impl Shutter {
+    pub fn new(open: f64, close: f64, curve: ShutterCurve) -> Self {
+        Self { open, close, curve }
+    }
+
+    /**
+     * Maps a uniform sample to a time within the exposure, distributed
+     * according to the shutter curve.
+     */
+    pub fn sample(&self, u: f64) -> f64 {
+        let x = match self.curve {
+            ShutterCurve::Box => u,
+            ShutterCurve::Trapezoid { ramp } => {
+                let ramp = ramp.clamp(0., 0.5);
+                if ramp == 0. {
+                    u
+                } else {
+                    // Invert the CDF of a trapezoid with height 1 / (1 - ramp).
+                    let height = 1. / (1. - ramp);
+                    let ramp_area = height * ramp / 2.;
+                    if u < ramp_area {
+                        (2. * ramp * u / height).sqrt()
+                    } else if u > 1. - ramp_area {
+                        1. - (2. * ramp * (1. - u) / height).sqrt()
+                    } else {
+                        u / height + ramp / 2.
+                    }
+                }
+            }
+        };
+
+        self.open + x * (self.close - self.open)
+    }
+
+    pub(crate) fn parameters(&self) -> [f64; 3] {
+        let ramp = match self.curve {
+            ShutterCurve::Box => 0.,
+            ShutterCurve::Trapezoid { ramp } => ramp,
+        };
+        [self.open, self.close, ramp]
+    }
+}
+
+impl Default for Shutter {
+    fn default() -> Self {
+        Self::new(0., 1., ShutterCurve::Box)
+    }
+}
+
+/**
+ * The camera placement at scene time `time`.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub struct CameraKeyframe {
+    pub time: f64,
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vfov: f64,
+}
+
+impl CameraKeyframe {
+    pub fn new(time: f64, lookfrom: Point3, lookat: Point3, vfov: f64) -> Self {
+        Self {
+            time,
+            lookfrom,
+            lookat,
+            vfov,
+        }
+    }
+
+    /**
+     * Linearly interpolates between the keyframes, sorted by time, holding
+     * the first and last ones before and after them.
+     */
+    pub fn interpolate(keyframes: &[CameraKeyframe], time: f64) -> CameraKeyframe {
+        let next = keyframes.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return keyframes[0];
+        }
+        if next == keyframes.len() {
+            return keyframes[next - 1];
+        }
+
+        let (a, b) = (keyframes[next - 1], keyframes[next]);
+        let t = (time - a.time) / (b.time - a.time);
+
+        CameraKeyframe {
+            time,
+            lookfrom: a.lookfrom + t * (b.lookfrom - a.lookfrom),
+            lookat: a.lookat + t * (b.lookat - a.lookat),
+            vfov: a.vfov + t * (b.vfov - a.vfov),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shutter_curves() {
+        for curve in [
+            ShutterCurve::Box,
+            ShutterCurve::Trapezoid { ramp: 0.2 },
+            ShutterCurve::Trapezoid { ramp: 0.5 },
+        ] {
+            let shutter = Shutter::new(0.25, 0.75, curve);
+            let n = 1000;
+            let times: Vec<f64> = (0..n)
+                .map(|k| shutter.sample((k as f64 + 0.5) / n as f64))
+                .collect();
+
+            assert!(times.windows(2).all(|pair| pair[0] <= pair[1]));
+            assert!(times.iter().all(|t| (0.25..=0.75).contains(t)));
+            let mean = times.iter().sum::<f64>() / n as f64;
+            assert!((mean - 0.5).abs() < 1e-6);
+        }
+
+        // A triangle shutter lets through an eighth of the light by the time
+        // it is a quarter of the way through.
+        let triangle = Shutter::new(0., 1., ShutterCurve::Trapezoid { ramp: 0.5 });
+        assert!((triangle.sample(0.125) - 0.25).abs() < 1e-12);
+        assert!((triangle.sample(0.5) - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_keyframe_interpolation() {
+        let keyframes = [
+            CameraKeyframe::new(0., Point3::new(0., 0., 0.), Point3::new(0., 0., -1.), 40.),
+            CameraKeyframe::new(1., Point3::new(2., 0., 0.), Point3::new(2., 0., -1.), 20.),
+        ];
+
+        let middle = CameraKeyframe::interpolate(&keyframes, 0.25);
+        assert!(middle.lookfrom == Point3::new(0.5, 0., 0.));
+        assert_eq!(middle.vfov, 35.);
+        assert_eq!(CameraKeyframe::interpolate(&keyframes, -1.).vfov, 40.);
+        assert_eq!(CameraKeyframe::interpolate(&keyframes, 2.).vfov, 20.);
+    }
+}
@@ -0,0 +1,69 @@
+use crate::vec3::Vec3;
+
+/**
+ * An orthonormal basis whose `w` axis points along a given direction, for
+ * turning directions sampled around the z axis into world space.
+ */
+#[derive(Clone, Copy)]
+pub struct Onb {
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Onb {
+    pub fn new(n: Vec3) -> Self {
+        let w = n.unit_vector();
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0., 1., 0.)
+        } else {
+            Vec3::new(1., 0., 0.)
+        };
+        let v = w.cross(a).unit_vector();
+        let u = w.cross(v);
+
+        Self { u, v, w }
+    }
+
+    pub fn u(&self) -> Vec3 {
+        self.u
+    }
+
+    pub fn v(&self) -> Vec3 {
+        self.v
+    }
+
+    pub fn w(&self) -> Vec3 {
+        self.w
+    }
+
+    /**
+     * The world space direction of `a`, given in the coordinates of the basis.
+     */
+    pub fn transform(&self, a: Vec3) -> Vec3 {
+        a[0] * self.u + a[1] * self.v + a[2] * self.w
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_onb_is_orthonormal() {
+        for n in [
+            Vec3::new(0., 0., 1.),
+            Vec3::new(1., 0., 0.),
+            Vec3::new(-0.3, 2., 0.7),
+        ] {
+            let uvw = Onb::new(n);
+            for axis in [uvw.u(), uvw.v(), uvw.w()] {
+                assert!((axis.length() - 1.).abs() < 1e-12);
+            }
+            assert!(uvw.u().dot(uvw.v()).abs() < 1e-12);
+            assert!(uvw.v().dot(uvw.w()).abs() < 1e-12);
+            assert!(uvw.w().dot(uvw.u()).abs() < 1e-12);
+            assert!((uvw.transform(Vec3::new(0., 0., 2.)) - 2. * n.unit_vector()).near_zero());
+        }
+    }
+}
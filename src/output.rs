@@ -0,0 +1,377 @@
+use crate::aov::{Aov, Aovs};
+use crate::color::{to_rgb16, to_rgb8, write_color};
+use crate::framebuffer::Framebuffer;
+use crate::stats::{self, Phase};
+use crate::vec3::Vec3;
+use exr::prelude::{AnyChannel, AnyChannels, FlatSamples, SmallVec, Text, WritableImage};
+use image::codecs::hdr::HdrEncoder;
+use image::error::{EncodingError, ImageFormatHint};
+use image::{ImageBuffer, ImageError, ImageFormat, ImageResult, Rgb};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// ASCII (P3) PPM, tone mapped to 8 bits.
+    Ppm,
+    /// Binary (P6) PPM, tone mapped to 8 bits.
+    PpmBinary,
+    /// Portable float map holding the linear radiance as 32-bit floats.
+    Pfm,
+    /// PNG, tone mapped to 8 bits per channel.
+    Png,
+    /// PNG, tone mapped to 16 bits per channel.
+    Png16,
+    /// Radiance HDR holding the linear radiance.
+    Hdr,
+    /// OpenEXR holding the linear radiance as 32-bit floats, along with
+    /// the AOVs of the image if it has them.
+    Exr,
+}
+
+impl OutputFormat {
+    /**
+     * Picks the output format from the file extension of `path`. PNG files
+     * are 8-bit; use `save_with_format` with `Png16` for 16 bits.
+     */
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+
+        match extension.as_str() {
+            "ppm" => Some(Self::PpmBinary),
+            "pfm" => Some(Self::Pfm),
+            "png" => Some(Self::Png),
+            "hdr" => Some(Self::Hdr),
+            "exr" => Some(Self::Exr),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * Writes the image as an ASCII (P3) PPM file.
+ */
+pub fn write_ppm(out: &mut dyn Write, image: &Framebuffer) -> io::Result<()> {
+    write!(out, "P3\n{} {}\n255\n", image.width(), image.height())?;
+
+    let display = image.display();
+    for &pixel_color in image.pixels() {
+        write_color(out, display.apply(pixel_color))?;
+    }
+
+    Ok(())
+}
+
+/**
+ * Writes the image as a binary (P6) PPM file.
+ */
+pub fn write_ppm_binary(out: &mut dyn Write, image: &Framebuffer) -> io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", image.width(), image.height())?;
+
+    let display = image.display();
+    let mut row_bytes = Vec::with_capacity(3 * image.width());
+    for row in image.rows() {
+        row_bytes.clear();
+        row_bytes.extend(row.iter().flat_map(|&c| to_rgb8(display.apply(c))));
+        out.write_all(&row_bytes)?;
+    }
+
+    Ok(())
+}
+
+/**
+ * Writes the linear image as a little-endian PFM file. PFM stores its rows
+ * from the bottom of the image to the top.
+ */
+pub fn write_pfm(out: &mut dyn Write, image: &Framebuffer) -> io::Result<()> {
+    write!(out, "PF\n{} {}\n-1.0\n", image.width(), image.height())?;
+
+    let mut row_bytes = Vec::with_capacity(12 * image.width());
+    for row in image.rows().rev() {
+        row_bytes.clear();
+        for c in row {
+            for component in [c.x(), c.y(), c.z()] {
+                row_bytes.extend_from_slice(&(component as f32).to_le_bytes());
+            }
+        }
+        out.write_all(&row_bytes)?;
+    }
+
+    Ok(())
+}
+
+/**
+ * Saves the image to `path`, choosing the format from its file extension.
+ */
+pub fn save<P: AsRef<Path>>(path: P, image: &Framebuffer) -> ImageResult<()> {
+    let path = path.as_ref();
+
+    match OutputFormat::from_path(path) {
+        Some(format) => save_with_format(path, image, format),
+        None => Err(ImageError::Unsupported(
+            ImageFormatHint::PathExtension(path.to_path_buf()).into(),
+        )),
+    }
+}
+
+pub fn save_with_format<P: AsRef<Path>>(
+    path: P,
+    image: &Framebuffer,
+    format: OutputFormat,
+) -> ImageResult<()> {
+    stats::time(Phase::Output, || encode(path.as_ref(), image, format))
+}
+
+fn encode(path: &Path, image: &Framebuffer, format: OutputFormat) -> ImageResult<()> {
+    let width = image.width() as u32;
+    let height = image.height() as u32;
+
+    match format {
+        OutputFormat::Ppm => write_to_file(path, image, write_ppm),
+        OutputFormat::PpmBinary => write_to_file(path, image, write_ppm_binary),
+        OutputFormat::Pfm => write_to_file(path, image, write_pfm),
+        OutputFormat::Png => {
+            let display = image.display();
+            let data = image
+                .pixels()
+                .iter()
+                .flat_map(|&c| to_rgb8(display.apply(c)))
+                .collect();
+            let buffer: ImageBuffer<Rgb<u8>, Vec<u8>> =
+                ImageBuffer::from_raw(width, height, data).expect("buffer matches image size");
+            buffer.save_with_format(path, ImageFormat::Png)
+        }
+        OutputFormat::Png16 => {
+            let display = image.display();
+            let data = image
+                .pixels()
+                .iter()
+                .flat_map(|&c| to_rgb16(display.apply(c)))
+                .collect();
+            let buffer: ImageBuffer<Rgb<u16>, Vec<u16>> =
+                ImageBuffer::from_raw(width, height, data).expect("buffer matches image size");
+            buffer.save_with_format(path, ImageFormat::Png)
+        }
+        OutputFormat::Hdr => {
+            let data: Vec<Rgb<f32>> = image
+                .pixels()
+                .iter()
+                .map(|c| Rgb([c.x() as f32, c.y() as f32, c.z() as f32]))
+                .collect();
+            let out = BufWriter::new(File::create(path)?);
+            HdrEncoder::new(out).encode(&data, image.width(), image.height())
+        }
+        OutputFormat::Exr if image.aovs().is_some() => {
+            write_exr_with_aovs(path, image, image.aovs().unwrap())
+        }
+        OutputFormat::Exr => {
+            let data = image
+                .pixels()
+                .iter()
+                .flat_map(|c| [c.x() as f32, c.y() as f32, c.z() as f32])
+                .collect();
+            let buffer: ImageBuffer<Rgb<f32>, Vec<f32>> =
+                ImageBuffer::from_raw(width, height, data).expect("buffer matches image size");
+            buffer.save_with_format(path, ImageFormat::OpenExr)
+        }
+    }
+}
+
+/**
+ * Writes the radiance as the R, G and B channels of an OpenEXR file, with
+ * every AOV as a group of channels named after it, such as `normal.X`.
+ */
+fn write_exr_with_aovs(path: &Path, image: &Framebuffer, aovs: &Aovs) -> ImageResult<()> {
+    let floats = |values: Vec<f64>| FlatSamples::F32(values.iter().map(|&x| x as f32).collect());
+    let component = |values: &[Vec3], k: usize| floats(values.iter().map(|v| v[k]).collect());
+    let group =
+        |aov: Aov, channel: &str| Text::from(format!("{}.{}", aov.name(), channel).as_str());
+
+    let mut channels = vec![
+        AnyChannel::new("R", component(image.pixels(), 0)),
+        AnyChannel::new("G", component(image.pixels(), 1)),
+        AnyChannel::new("B", component(image.pixels(), 2)),
+        AnyChannel::new(group(Aov::Depth, "Z"), floats(aovs.depth().to_vec())),
+    ];
+    for (aov, values, names) in [
+        (Aov::Normal, aovs.normal(), ["X", "Y", "Z"]),
+        (Aov::Position, aovs.position(), ["X", "Y", "Z"]),
+        (Aov::Albedo, aovs.albedo(), ["R", "G", "B"]),
+    ] {
+        for (k, name) in names.iter().enumerate() {
+            channels.push(AnyChannel::new(group(aov, name), component(values, k)));
+        }
+    }
+    let (u, v) = aovs.uv().iter().copied().unzip();
+    channels.push(AnyChannel::new(group(Aov::Uv, "U"), floats(u)));
+    channels.push(AnyChannel::new(group(Aov::Uv, "V"), floats(v)));
+    for (aov, ids) in [
+        (Aov::ObjectId, aovs.object_id()),
+        (Aov::MaterialId, aovs.material_id()),
+    ] {
+        channels.push(AnyChannel::new(
+            group(aov, "id"),
+            FlatSamples::U32(ids.to_vec()),
+        ));
+    }
+
+    exr::image::Image::from_channels(
+        (image.width(), image.height()),
+        AnyChannels::sort(SmallVec::from_vec(channels)),
+    )
+    .write()
+    .to_file(path)
+    .map_err(|e| {
+        ImageError::Encoding(EncodingError::new(
+            ImageFormatHint::Exact(ImageFormat::OpenExr),
+            e,
+        ))
+    })
+}
+
+fn write_to_file(
+    path: &Path,
+    image: &Framebuffer,
+    writer: fn(&mut dyn Write, &Framebuffer) -> io::Result<()>,
+) -> ImageResult<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writer(&mut out, image)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    fn gradient() -> Framebuffer {
+        let mut image = Framebuffer::new(4, 3);
+        for y in 0..3 {
+            for x in 0..4 {
+                image.set_pixel(x, y, Color::new(x as f64 * 0.25, y as f64 * 2., 4.));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_png_is_tonemapped() {
+        let path = std::env::temp_dir().join("ray_tracing_test_png_is_tonemapped.png");
+        save(&path, &gradient()).expect("failed to save png");
+
+        let loaded = image::open(&path).expect("failed to load png").to_rgb8();
+        assert_eq!(loaded.dimensions(), (4, 3));
+        assert_eq!(loaded.get_pixel(1, 0).0, [137, 0, 255]);
+        assert_eq!(loaded.get_pixel(3, 2).0, [225, 255, 255]);
+    }
+
+    #[test]
+    fn test_png16() {
+        // The extension alone always picks 8 bits.
+        assert_eq!(
+            OutputFormat::from_path("image.png"),
+            Some(OutputFormat::Png)
+        );
+        let path = std::env::temp_dir().join("ray_tracing_test_png16.png");
+        save_with_format(&path, &gradient(), OutputFormat::Png16).expect("failed to save png");
+
+        let loaded = image::open(&path).expect("failed to load png");
+        assert_eq!(loaded.color(), image::ColorType::Rgb16);
+        // Finer than the 8-bit steps, which are multiples of 257.
+        assert_eq!(loaded.to_rgb16().get_pixel(3, 2).0, [57725, 65535, 65535]);
+    }
+
+    #[test]
+    fn test_exr_keeps_radiance() {
+        let path = std::env::temp_dir().join("ray_tracing_test_exr_keeps_radiance.exr");
+        save(&path, &gradient()).expect("failed to save exr");
+
+        let loaded = image::open(&path).expect("failed to load exr").to_rgb32f();
+        assert_eq!(loaded.dimensions(), (4, 3));
+        assert_eq!(loaded.get_pixel(3, 2).0, [0.75, 4., 4.]);
+    }
+
+    #[test]
+    fn test_exr_aov_channels() {
+        use crate::aov::AovSample;
+        use exr::prelude::read_all_flat_layers_from_file;
+
+        let pixels = (0..12)
+            .map(|k| AovSample {
+                depth: k as f64,
+                object_key: Some(k % 2),
+                ..Default::default()
+            })
+            .collect();
+        let mut image = gradient();
+        image.set_aovs(Aovs::new(4, 3, pixels));
+
+        let path = std::env::temp_dir().join("ray_tracing_test_exr_aov_channels.exr");
+        save(&path, &image).expect("failed to save exr");
+
+        let loaded = read_all_flat_layers_from_file(&path).expect("failed to load exr");
+        let channels = &loaded.layer_data[0].channel_data.list;
+        let channel = |name: &str| {
+            channels
+                .iter()
+                .find(|c| c.name == *name)
+                .unwrap_or_else(|| panic!("missing channel {}", name))
+        };
+
+        assert_eq!(channels.len(), 17);
+        assert_eq!(
+            channel("B").sample_data.value_by_flat_index(11).to_f32(),
+            4.
+        );
+        assert_eq!(
+            channel("depth.Z")
+                .sample_data
+                .value_by_flat_index(5)
+                .to_f32(),
+            5.
+        );
+        assert_eq!(
+            channel("object_id.id")
+                .sample_data
+                .value_by_flat_index(3)
+                .to_u32(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_ppm_binary() {
+        let mut out = Vec::new();
+        write_ppm_binary(&mut out, &gradient()).unwrap();
+
+        let header = b"P6\n4 3\n255\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(out.len(), header.len() + 4 * 3 * 3);
+        assert_eq!(&out[header.len() + 3..header.len() + 6], [137, 0, 255]);
+    }
+
+    #[test]
+    fn test_pfm_rows_bottom_up() {
+        let mut out = Vec::new();
+        write_pfm(&mut out, &gradient()).unwrap();
+
+        let header = b"PF\n4 3\n-1.0\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(out.len(), header.len() + 4 * 3 * 12);
+
+        // The first stored pixel is the bottom-left one.
+        let first_green = &out[header.len() + 4..header.len() + 8];
+        assert_eq!(f32::from_le_bytes(first_green.try_into().unwrap()), 4.);
+    }
+
+    #[test]
+    fn test_unknown_extension() {
+        assert_eq!(
+            OutputFormat::from_path("image.HDR"),
+            Some(OutputFormat::Hdr)
+        );
+        assert!(save("image.bmp", &gradient()).is_err());
+    }
+}
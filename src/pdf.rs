@@ -0,0 +1,247 @@
+use crate::hittable::Hittable;
+use crate::onb::Onb;
+use crate::sampler::Sampler;
+use crate::vec3::{Point3, Vec3};
+use crate::PI;
+
+/**
+ * A probability density over directions, which can be evaluated and sampled
+ * for importance sampling.
+ */
+pub trait Pdf {
+    /**
+     * The density of `direction` with respect to solid angle.
+     */
+    fn value(&self, direction: Vec3) -> f64;
+
+    /**
+     * Draws a direction distributed according to the density.
+     */
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3;
+}
+
+/**
+ * Uniform over all directions.
+ */
+#[derive(Clone, Copy, Default)]
+pub struct SpherePdf;
+
+impl Pdf for SpherePdf {
+    fn value(&self, _direction: Vec3) -> f64 {
+        1. / (4. * PI)
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        Vec3::sample_unit_vector(sampler.get_2d())
+    }
+}
+
+/**
+ * Proportional to the cosine to a surface normal, over the hemisphere it
+ * points into.
+ */
+#[derive(Clone, Copy)]
+pub struct CosinePdf {
+    uvw: Onb,
+}
+
+impl CosinePdf {
+    pub fn new(w: Vec3) -> Self {
+        Self { uvw: Onb::new(w) }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: Vec3) -> f64 {
+        let cosine_theta = direction.unit_vector().dot(self.uvw.w());
+        (cosine_theta / PI).max(0.)
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        self.uvw
+            .transform(Vec3::sample_cosine_direction(sampler.get_2d()))
+    }
+}
+
+/**
+ * The directions towards a uniform point in the ball of radius `fuzz` around
+ * the unit vector `center`, as fuzzy metal reflects. The density of a
+ * direction is the volume of the ball along it, `(t1³ - t0³) / 3` for the
+ * part [t0, t1] of the ray inside the ball, divided by the ball's volume.
+ */
+#[derive(Clone, Copy)]
+pub struct FuzzPdf {
+    center: Vec3,
+    fuzz: f64,
+}
+
+impl FuzzPdf {
+    pub fn new(center: Vec3, fuzz: f64) -> Self {
+        Self { center, fuzz }
+    }
+}
+
+impl Pdf for FuzzPdf {
+    fn value(&self, direction: Vec3) -> f64 {
+        let b = direction.unit_vector().dot(self.center);
+        let discriminant = b * b - self.center.length_squared() + self.fuzz * self.fuzz;
+        if discriminant <= 0. {
+            return 0.;
+        }
+        let t0 = (b - discriminant.sqrt()).max(0.);
+        let t1 = (b + discriminant.sqrt()).max(0.);
+
+        (t1.powi(3) - t0.powi(3)) / (4. * PI * self.fuzz.powi(3))
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        self.center + self.fuzz * Vec3::sample_in_unit_sphere(sampler.get_2d(), sampler.get_1d())
+    }
+}
+
+/**
+ * The directions from `origin` towards points on `objects`, such as the
+ * lights of a scene.
+ */
+pub struct HittablePdf<'a> {
+    objects: &'a dyn Hittable,
+    origin: Point3,
+}
+
+impl<'a> HittablePdf<'a> {
+    pub fn new(objects: &'a dyn Hittable, origin: Point3) -> Self {
+        Self { objects, origin }
+    }
+}
+
+impl Pdf for HittablePdf<'_> {
+    fn value(&self, direction: Vec3) -> f64 {
+        self.objects.pdf_value(self.origin, direction)
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        self.objects.random(self.origin, sampler)
+    }
+}
+
+/**
+ * An equal mix of two densities, sampling either one half of the time.
+ */
+pub struct MixturePdf<'a> {
+    p: [&'a dyn Pdf; 2],
+}
+
+impl<'a> MixturePdf<'a> {
+    pub fn new(p0: &'a dyn Pdf, p1: &'a dyn Pdf) -> Self {
+        Self { p: [p0, p1] }
+    }
+}
+
+impl Pdf for MixturePdf<'_> {
+    fn value(&self, direction: Vec3) -> f64 {
+        0.5 * self.p[0].value(direction) + 0.5 * self.p[1].value(direction)
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        if sampler.get_1d() < 0.5 {
+            self.p[0].generate(sampler)
+        } else {
+            self.p[1].generate(sampler)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::material::DiffuseLight;
+    use crate::quad::Quad;
+    use crate::sampler::IndependentSampler;
+    use crate::seed_random;
+    use crate::sphere::Sphere;
+    use std::sync::Arc;
+
+    /**
+     * Estimates the integral of `f` over the sphere by sampling `pdf`.
+     */
+    fn estimate(pdf: &dyn Pdf, f: impl Fn(Vec3) -> f64) -> f64 {
+        seed_random(7);
+        let mut sampler = IndependentSampler;
+        let n = 100_000;
+        (0..n)
+            .map(|_| {
+                let direction = pdf.generate(&mut sampler);
+                f(direction) / pdf.value(direction)
+            })
+            .sum::<f64>()
+            / n as f64
+    }
+
+    #[test]
+    fn test_pdfs_integrate_cosine() {
+        // The cosine over the upper hemisphere integrates to pi.
+        let normal = Vec3::new(0.3, 1., -0.2).unit_vector();
+        let cosine = |d: Vec3| d.unit_vector().dot(normal).max(0.);
+
+        let cosine_pdf = CosinePdf::new(normal);
+        assert!((estimate(&cosine_pdf, cosine) - PI).abs() < 1e-9);
+        assert!((estimate(&SpherePdf, cosine) - PI).abs() < 0.05);
+        let mixture = MixturePdf::new(&cosine_pdf, &SpherePdf);
+        assert!((estimate(&mixture, cosine) - PI).abs() < 0.05);
+
+        // The fuzz lobe is normalized over the sphere and matches what it
+        // generates, also when the ball contains the origin.
+        for fuzz in [0.3, 1.5] {
+            let lobe = FuzzPdf::new(Vec3::new(0., 0.6, 0.8), fuzz);
+            assert!((estimate(&SpherePdf, |d| lobe.value(d)) - 1.).abs() < 0.02);
+            let cosine = |d: Vec3| d.unit_vector()[1];
+            let expected = estimate(&SpherePdf, |d| lobe.value(d) * cosine(d));
+            let sampled = estimate(&lobe, |d| lobe.value(d) * cosine(d));
+            assert!((sampled - expected).abs() < 0.02);
+        }
+    }
+
+    #[test]
+    fn test_hittable_pdf_matches_cosine_sampling() {
+        // The cosine-weighted solid angle of a light seen from the origin,
+        // estimated once by sampling the light and once by sampling the
+        // cosine.
+        let light = Arc::new(DiffuseLight::new_with_color(Color::new(1., 1., 1.)));
+        let lights: [Box<dyn Hittable>; 2] = [
+            Box::new(Sphere::new(Point3::new(0.5, 2., 0.3), 0.6, light.clone())),
+            Box::new(Quad::new(
+                Point3::new(-1., 1.5, -0.5),
+                Vec3::new(1., 0., 0.),
+                Vec3::new(0., 0.2, 1.),
+                light,
+            )),
+        ];
+        let normal = Vec3::new(0., 1., 0.);
+        let origin = Point3::new(0., 0., 0.);
+
+        for object in &lights {
+            let visible = |d: Vec3| {
+                let cosine = d.unit_vector().dot(normal).max(0.);
+                if object.pdf_value(origin, d) > 0. {
+                    cosine
+                } else {
+                    0.
+                }
+            };
+            let sampled = estimate(&HittablePdf::new(&**object, origin), visible);
+            let reference = estimate(&CosinePdf::new(normal), visible);
+            assert!(sampled > 0.1);
+            assert!((sampled - reference).abs() < 0.02 * reference);
+        }
+    }
+
+    #[test]
+    fn test_sphere_pdf_from_inside() {
+        // From inside a sphere light every direction hits it.
+        let light = Arc::new(DiffuseLight::new_with_color(Color::new(1., 1., 1.)));
+        let sphere = Sphere::new(Point3::new(0., 0., 0.), 2., light);
+        let pdf = HittablePdf::new(&sphere, Point3::new(0.5, -0.3, 0.2));
+        assert!((estimate(&pdf, |_| 1.) - 4. * PI).abs() < 1e-9);
+    }
+}
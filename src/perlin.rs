@@ -1,9 +1,8 @@
+use crate::random_int;
 use crate::vec3::*;
-use ray_tracing::random_int;
 
 #[derive(Clone, Copy)]
 pub struct Perlin {
-    point_count: usize,
     ranvec: [Vec3; Self::POINT_COUNT],
     perm_x: [usize; Self::POINT_COUNT],
     perm_y: [usize; Self::POINT_COUNT],
@@ -20,8 +19,6 @@ impl Perlin {
     const POINT_COUNT: usize = 256;
 
     pub fn new() -> Self {
-        let point_count = Self::POINT_COUNT;
-
         let mut ranvec = [Vec3::default(); Self::POINT_COUNT];
         for i in &mut ranvec {
             *i = Vec3::random_range(-1., 1.).unit_vector();
@@ -32,7 +29,6 @@ impl Perlin {
         let perm_z = Self::perlin_generate_perm();
 
         Self {
-            point_count,
             ranvec,
             perm_x,
             perm_y,
@@ -40,6 +36,7 @@ impl Perlin {
         }
     }
 
+    #[allow(clippy::needless_range_loop)]
     pub fn noise(&self, p: Point3) -> f64 {
         let u = p.x() - p.x().floor();
         let v = p.y() - p.y().floor();
@@ -82,6 +79,7 @@ impl Perlin {
         }
     }
 
+    #[allow(clippy::needless_range_loop)]
     fn perlin_interp(c: &mut [[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
         let uu = u * u * (3. - 2. * u);
         let vv = v * v * (3. - 2. * v);
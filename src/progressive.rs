@@ -0,0 +1,265 @@
+use crate::adaptive::PixelStatistics;
+use crate::color::Color;
+use crate::film::Film;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const MAGIC: &[u8; 8] = b"RTCHKPT2";
+
+/// The size of the header after the magic number and of every pixel record.
+const HEADER_BYTES: u64 = 5 * 8;
+const PIXEL_BYTES: u64 = 4 * 8 + 4 + 2 * 8;
+
+/**
+ * Settings for `Camera::render_progressive`: the image is refined in passes
+ * that add up to `pass_samples` to every pixel, writing a checkpoint to
+ * `checkpoint` at most every `checkpoint_interval` and once more at the end.
+ * With `resume` an existing checkpoint is continued instead of overwritten.
+ * It is off by default, since the scene hash does not notice every edit to
+ * the world and a stale checkpoint would bring back the old image.
+ */
+#[derive(Clone, Debug)]
+pub struct ProgressiveSettings {
+    pub pass_samples: i32,
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_interval: Duration,
+    pub resume: bool,
+}
+
+impl Default for ProgressiveSettings {
+    fn default() -> Self {
+        Self {
+            pass_samples: 16,
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(60),
+            resume: false,
+        }
+    }
+}
+
+/**
+ * The accumulated state of a progressive render. Every sample draws from a
+ * random stream derived from the seed, the pixel and the sample index, so the
+ * seed, the per-pixel sample counts and the number of samples the sampler
+ * stratifies are all the random state needed to continue exactly where the
+ * render stopped.
+ */
+#[derive(Clone)]
+pub struct Checkpoint {
+    seed: u64,
+    scene_hash: u64,
+    strata: i32,
+    pub(crate) film: Film,
+    pub(crate) stats: Vec<PixelStatistics>,
+}
+
+impl Checkpoint {
+    pub fn new(width: usize, height: usize, seed: u64, scene_hash: u64, strata: i32) -> Self {
+        Self {
+            seed,
+            scene_hash,
+            strata,
+            film: Film::new(width, height),
+            stats: vec![PixelStatistics::default(); width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.film.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.film.height()
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn scene_hash(&self) -> u64 {
+        self.scene_hash
+    }
+
+    /**
+     * The number of samples per pixel the sampler was stratified for when the
+     * render was started.
+     */
+    pub fn strata(&self) -> i32 {
+        self.strata
+    }
+
+    pub fn sample_counts(&self) -> Vec<u32> {
+        self.stats.iter().map(|stats| stats.count()).collect()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let length = file.metadata()?.len();
+        let checkpoint = Self::read_from(&mut BufReader::new(file))?;
+        if length != MAGIC.len() as u64 + HEADER_BYTES + checkpoint.size_bytes() {
+            return Err(invalid_data("checkpoint has trailing data"));
+        }
+
+        Ok(checkpoint)
+    }
+
+    /**
+     * Writes next to `path` first and then renames, so a crash while saving
+     * never destroys the previous checkpoint.
+     */
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let partial = path.with_extension("partial");
+
+        let mut out = BufWriter::new(File::create(&partial)?);
+        self.write_to(&mut out)?;
+        out.into_inner()?.sync_all()?;
+
+        fs::rename(partial, path)
+    }
+
+    /**
+     * Writes the checkpoint in a little-endian binary layout: a magic number,
+     * width, height, seed, scene hash and strata, followed by the accumulated
+     * radiance, filter weight and sample statistics of every pixel.
+     */
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        for value in [
+            self.width() as u64,
+            self.height() as u64,
+            self.seed,
+            self.scene_hash,
+            self.strata as u64,
+        ] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+
+        for ((sum, weight), stats) in self.film.sum.iter().zip(&self.film.weight).zip(&self.stats) {
+            for value in [sum.x(), sum.y(), sum.z(), *weight] {
+                out.write_all(&value.to_le_bytes())?;
+            }
+            out.write_all(&stats.count.to_le_bytes())?;
+            out.write_all(&stats.mean.to_le_bytes())?;
+            out.write_all(&stats.m2.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Reads a checkpoint written by `write_to`. The pixel data is read before
+     * the image is allocated, so a corrupt size fails on the missing data
+     * instead of allocating whatever the header claims.
+     */
+    pub fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a render checkpoint"));
+        }
+
+        let width = read_u64(input)?;
+        let height = read_u64(input)?;
+        let seed = read_u64(input)?;
+        let scene_hash = read_u64(input)?;
+        let strata = read_u64(input)?;
+        let size = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(PIXEL_BYTES))
+            .ok_or_else(|| invalid_data("checkpoint image size is too large"))?;
+
+        let mut data = Vec::new();
+        input.take(size).read_to_end(&mut data)?;
+        if data.len() as u64 != size {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let (width, height) = (width as usize, height as usize);
+        let strata = i32::try_from(strata).map_err(|_| invalid_data("invalid strata"))?;
+        let mut checkpoint = Self::new(width, height, seed, scene_hash, strata);
+        let mut data = data.as_slice();
+        for k in 0..width * height {
+            checkpoint.film.sum[k] = Color::new(
+                read_f64(&mut data)?,
+                read_f64(&mut data)?,
+                read_f64(&mut data)?,
+            );
+            checkpoint.film.weight[k] = read_f64(&mut data)?;
+            checkpoint.stats[k] = PixelStatistics {
+                count: read_u32(&mut data)?,
+                mean: read_f64(&mut data)?,
+                m2: read_f64(&mut data)?,
+            };
+        }
+
+        Ok(checkpoint)
+    }
+
+    fn size_bytes(&self) -> u64 {
+        (self.width() * self.height()) as u64 * PIXEL_BYTES
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64<R: Read>(input: &mut R) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let mut checkpoint = Checkpoint::new(3, 2, 42, 0xdead_beef, 16);
+        for k in 0..6 {
+            checkpoint.film.sum[k] = Color::new(k as f64, 0.5, 1e-20);
+            checkpoint.film.weight[k] = 0.25 * k as f64;
+            checkpoint.stats[k].add(Color::new(1., 2., 3.));
+        }
+
+        let mut bytes = Vec::new();
+        checkpoint.write_to(&mut bytes).unwrap();
+        let loaded = Checkpoint::read_from(&mut bytes.as_slice()).unwrap();
+
+        assert_eq!((loaded.width(), loaded.height()), (3, 2));
+        assert_eq!((loaded.seed(), loaded.scene_hash()), (42, 0xdead_beef));
+        assert_eq!(loaded.strata(), 16);
+        assert_eq!(loaded.sample_counts(), vec![1; 6]);
+        assert!(loaded.film.sum == checkpoint.film.sum);
+        assert_eq!(loaded.film.weight, checkpoint.film.weight);
+        assert_eq!(loaded.stats[5].mean(), checkpoint.stats[5].mean());
+
+        assert!(Checkpoint::read_from(&mut &bytes[1..]).is_err());
+        assert!(Checkpoint::read_from(&mut &bytes[..bytes.len() - 1]).is_err());
+
+        // A corrupt size is rejected before anything that large is allocated.
+        let mut huge = bytes.clone();
+        huge[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        let error = Checkpoint::read_from(&mut huge.as_slice()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        huge[8..16].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        let error = Checkpoint::read_from(&mut huge.as_slice()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::vec3::Vec3;
+use crate::{degrees_to_radians, PI};
+
+/**
+ * How the camera maps the image onto rays.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Projection {
+    /// Thin lens perspective using `vfov`, `defocus_angle` and `focus_dist`.
+    #[default]
+    Perspective,
+    /// Parallel rays along the view direction through a view `height` world
+    /// units tall.
+    Orthographic { height: f64 },
+    /// A circular fisheye covering `fov` degrees across the shorter side of
+    /// the image. Pixels outside the circle stay black.
+    Fisheye { fov: f64, mapping: FisheyeMapping },
+    /// All directions around the camera, with longitude running across the
+    /// image and latitude down it. Meant for images twice as wide as tall.
+    Equirectangular,
+}
+
+/**
+ * How the angle from the view direction grows with the distance from the
+ * center of a fisheye image.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FisheyeMapping {
+    /// The angle grows linearly with the distance.
+    #[default]
+    Equidistant,
+    /// Every pixel covers the same solid angle.
+    Equisolid,
+}
+
+impl Projection {
+    /**
+     * The direction of the ray through the film position (s, t), both in
+     * [0, 1] from the top-left corner, for the panoramic projections. The
+     * direction is in camera space: x to the right, y up and the view
+     * direction along -z. Returns `None` where the projection does not cover
+     * the image.
+     */
+    pub(crate) fn direction(&self, s: f64, t: f64, aspect_ratio: f64) -> Option<Vec3> {
+        match *self {
+            Projection::Fisheye { fov, mapping } => {
+                // Scale so the shorter side of the image spans [-1, 1].
+                let (x, y) = if aspect_ratio >= 1. {
+                    ((2. * s - 1.) * aspect_ratio, 1. - 2. * t)
+                } else {
+                    (2. * s - 1., (1. - 2. * t) / aspect_ratio)
+                };
+                let r = x.hypot(y);
+                if r > 1. {
+                    return None;
+                }
+
+                let half_fov = degrees_to_radians(fov) / 2.;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * half_fov,
+                    FisheyeMapping::Equisolid => 2. * (r * (half_fov / 2.).sin()).asin(),
+                };
+                let phi = y.atan2(x);
+
+                Some(Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    -theta.cos(),
+                ))
+            }
+            Projection::Equirectangular => {
+                let phi = 2. * PI * (s - 0.5);
+                let theta = PI * (0.5 - t);
+
+                Some(Vec3::new(
+                    theta.cos() * phi.sin(),
+                    theta.sin(),
+                    -theta.cos() * phi.cos(),
+                ))
+            }
+            Projection::Perspective | Projection::Orthographic { .. } => {
+                Some(Vec3::new(0., 0., -1.))
+            }
+        }
+    }
+
+    /**
+     * Numbers identifying the projection and its parameters.
+     */
+    pub(crate) fn parameters(&self) -> [f64; 3] {
+        match *self {
+            Projection::Perspective => [0., 0., 0.],
+            Projection::Orthographic { height } => [1., height, 0.],
+            Projection::Fisheye { fov, mapping } => [2., fov, mapping as u8 as f64],
+            Projection::Equirectangular => [3., 0., 0.],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_fisheye_directions() {
+        for mapping in [FisheyeMapping::Equidistant, FisheyeMapping::Equisolid] {
+            let fisheye = Projection::Fisheye { fov: 180., mapping };
+            assert_near(
+                fisheye.direction(0.5, 0.5, 1.).unwrap(),
+                Vec3::new(0., 0., -1.),
+            );
+            // The edge of the circle looks sideways, the corners are outside.
+            assert_near(
+                fisheye.direction(1., 0.5, 1.).unwrap(),
+                Vec3::new(1., 0., 0.),
+            );
+            assert_near(
+                fisheye.direction(0.5, 0., 1.).unwrap(),
+                Vec3::new(0., 1., 0.),
+            );
+            assert!(fisheye.direction(0.99, 0.99, 1.).is_none());
+        }
+    }
+
+    #[test]
+    fn test_equirectangular_directions() {
+        let projection = Projection::Equirectangular;
+        assert_near(
+            projection.direction(0.5, 0.5, 2.).unwrap(),
+            Vec3::new(0., 0., -1.),
+        );
+        assert_near(
+            projection.direction(0.75, 0.5, 2.).unwrap(),
+            Vec3::new(1., 0., 0.),
+        );
+        assert_near(
+            projection.direction(0., 0.5, 2.).unwrap(),
+            Vec3::new(0., 0., 1.),
+        );
+        assert_near(
+            projection.direction(0.3, 0., 2.).unwrap(),
+            Vec3::new(0., 1., 0.),
+        );
+    }
+}
@@ -4,7 +4,10 @@ use crate::hittable_list::*;
 use crate::interval::*;
 use crate::material::*;
 use crate::ray::*;
+use crate::sampler::Sampler;
+use crate::stats::{self, Primitive};
 use crate::vec3::*;
+use crate::INFINITY;
 use std::sync::Arc;
 
 #[derive(Clone)]
@@ -15,8 +18,10 @@ pub struct Quad {
     w: Vec3,
     normal: Vec3,
     d: f64,
+    area: f64,
     mat: Arc<dyn Material + Send>,
     bbox: Aabb,
+    id: u64,
 }
 
 impl Quad {
@@ -33,8 +38,10 @@ impl Quad {
             w,
             normal,
             d,
+            area: n.length(),
             mat,
             bbox: Aabb::default(),
+            id: object_id(&[q[0], q[1], q[2], u[0], u[1], u[2], v[0], v[1], v[2]]),
         };
         quad.set_bounding_box();
 
@@ -58,6 +65,7 @@ impl Quad {
 
 impl Hittable for Quad {
     fn hit(&self, r: Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        stats::intersection_test(Primitive::Quad);
         let denom = self.normal.dot(r.direction());
 
         // if ray is parallel
@@ -84,6 +92,7 @@ impl Hittable for Quad {
         rec.t = t;
         rec.p = intersection;
         rec.mat = self.mat.clone();
+        rec.object_id = self.id;
         rec.set_face_normal(r, self.normal);
 
         true
@@ -92,6 +101,37 @@ impl Hittable for Quad {
     fn bounding_box(&self) -> Aabb {
         self.bbox
     }
+
+    /**
+     * Points are sampled uniformly over the area, so the density is the area
+     * density converted to solid angle.
+     */
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        let mut rec = HitRecord::default();
+        if !self.hit(
+            Ray::new(origin, direction),
+            Interval::new(0.001, INFINITY),
+            &mut rec,
+        ) {
+            return 0.;
+        }
+
+        let distance_squared = rec.t * rec.t * direction.length_squared();
+        let cosine = (direction.dot(rec.normal) / direction.length()).abs();
+
+        distance_squared / (cosine * self.area)
+    }
+
+    fn random(&self, origin: Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        let (a, b) = sampler.get_2d();
+        self.q + a * self.u + b * self.v - origin
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        if self.mat.is_emissive() {
+            lights.add(Box::new(self.clone()));
+        }
+    }
 }
 
 pub fn r#box(a: Point3, b: Point3, mat: Arc<dyn Material + Send>) -> Box<dyn Hittable> {
@@ -0,0 +1,465 @@
+use crate::{mix_seed, random_double};
+use std::sync::OnceLock;
+
+const ONE_MINUS_EPSILON: f64 = 1. - f64::EPSILON / 2.;
+
+/**
+ * A source of sample values in [0, 1). Each pixel sample starts a fresh
+ * sequence of dimensions, which the camera and the materials consume in the
+ * same order for every sample.
+ */
+pub trait Sampler {
+    fn start_pixel_sample(&mut self, i: i32, j: i32, index: i32);
+
+    fn get_1d(&mut self) -> f64;
+
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SamplerType {
+    /// Uniform random numbers for every dimension.
+    #[default]
+    Independent,
+    /// Jittered strata over the pixel samples, shuffled per dimension.
+    Stratified,
+    /// The Halton sequence with Owen scrambling per pixel.
+    Halton,
+    /// Owen-scrambled Sobol (0,2)-sequence, padded across dimension pairs.
+    Sobol,
+}
+
+impl SamplerType {
+    pub fn create(self, samples_per_pixel: i32, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerType::Independent => Box::new(IndependentSampler),
+            SamplerType::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerType::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerType::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+/**
+ * Draws from the per-sample random stream the camera seeds for every pixel
+ * sample.
+ */
+#[derive(Clone, Copy, Default)]
+pub struct IndependentSampler;
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, _i: i32, _j: i32, _index: i32) {}
+
+    fn get_1d(&mut self) -> f64 {
+        random_double()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (random_double(), random_double())
+    }
+}
+
+/**
+ * Splits every dimension into as many strata as there are pixel samples and
+ * jitters inside them. 2D samples use a grid of `columns` x `rows` strata
+ * with exactly one stratum per sample. Samples past the planned count fall
+ * back to uniform random numbers.
+ */
+#[derive(Clone, Copy)]
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    columns: u32,
+    rows: u32,
+    seed: u64,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: i32, seed: u64) -> Self {
+        let samples_per_pixel = samples_per_pixel.max(1) as u32;
+        // The grid closest to square whose cells are all used: the rows are
+        // the largest factor of the sample count up to its square root.
+        let rows = (1..=samples_per_pixel.isqrt())
+            .rev()
+            .find(|&rows| samples_per_pixel.is_multiple_of(rows))
+            .unwrap_or(1);
+
+        Self {
+            samples_per_pixel,
+            columns: samples_per_pixel / rows,
+            rows,
+            seed,
+            pixel_seed: seed,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn stratum(&mut self) -> Option<u32> {
+        let hash = mix_seed(self.pixel_seed, self.dimension);
+        self.dimension += 1;
+
+        if self.index >= self.samples_per_pixel {
+            return None;
+        }
+
+        Some(permutation_element(
+            self.index,
+            self.samples_per_pixel,
+            hash as u32,
+        ))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, i: i32, j: i32, index: i32) {
+        self.pixel_seed = pixel_seed(self.seed, i, j);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        match self.stratum() {
+            Some(stratum) => (stratum as f64 + random_double()) / self.samples_per_pixel as f64,
+            None => random_double(),
+        }
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        match self.stratum() {
+            Some(stratum) => {
+                let x = (stratum % self.columns) as f64 + random_double();
+                let y = (stratum / self.columns) as f64 + random_double();
+                (x / self.columns as f64, y / self.rows as f64)
+            }
+            None => (random_double(), random_double()),
+        }
+    }
+}
+
+/**
+ * The Halton sequence with one prime base per dimension. Every pixel gets its
+ * own Owen scrambling of the digits; dimensions past the prime table fall back
+ * to uniform random numbers.
+ */
+#[derive(Clone, Copy)]
+pub struct HaltonSampler {
+    seed: u64,
+    pixel_seed: u64,
+    index: u64,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    const MAX_DIMENSIONS: usize = 1000;
+
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel_seed: seed,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn primes() -> &'static [u64] {
+        static PRIMES: OnceLock<Vec<u64>> = OnceLock::new();
+        PRIMES.get_or_init(|| {
+            let mut primes = Vec::with_capacity(Self::MAX_DIMENSIONS);
+            let mut candidate = 2;
+            while primes.len() < Self::MAX_DIMENSIONS {
+                if primes.iter().all(|p| candidate % p != 0) {
+                    primes.push(candidate);
+                }
+                candidate += 1;
+            }
+            primes
+        })
+    }
+
+    fn next(&mut self) -> f64 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+
+        match Self::primes().get(dimension) {
+            Some(&base) => owen_scrambled_radical_inverse(
+                base,
+                self.index,
+                mix_seed(self.pixel_seed, dimension as u64),
+            ),
+            None => random_double(),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, i: i32, j: i32, index: i32) {
+        self.pixel_seed = pixel_seed(self.seed, i, j);
+        self.index = index as u64;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.next()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.next(), self.next())
+    }
+}
+
+/**
+ * The first two dimensions of the Sobol sequence, a (0,2)-sequence, reused for
+ * every pair of dimensions. Each pair shuffles the sample order and scrambles
+ * the points with its own Owen scrambling, which keeps the pairs decorrelated
+ * without losing their stratification.
+ */
+#[derive(Clone, Copy)]
+pub struct SobolSampler {
+    seed: u64,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel_seed: seed,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn shuffled_index(&mut self) -> (u32, u64) {
+        let hash = mix_seed(self.pixel_seed, self.dimension);
+        self.dimension += 1;
+
+        // Flipping each index bit based on the bits above it moves aligned
+        // blocks of 2^k samples onto other aligned blocks, and those are
+        // (0,2)-nets too.
+        (owen_scramble(self.index, hash), hash)
+    }
+
+    /**
+     * The second Sobol dimension; the first is the bit-reversed index.
+     */
+    fn sobol_second(mut index: u32) -> u32 {
+        let mut v = 1u32 << 31;
+        let mut result = 0;
+        while index != 0 {
+            if index & 1 != 0 {
+                result ^= v;
+            }
+            index >>= 1;
+            v ^= v >> 1;
+        }
+        result
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, i: i32, j: i32, index: i32) {
+        self.pixel_seed = pixel_seed(self.seed, i, j);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (index, hash) = self.shuffled_index();
+        to_unit(owen_scramble(index.reverse_bits(), mix_seed(hash, 0)))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (index, hash) = self.shuffled_index();
+        (
+            to_unit(owen_scramble(index.reverse_bits(), mix_seed(hash, 0))),
+            to_unit(owen_scramble(Self::sobol_second(index), mix_seed(hash, 1))),
+        )
+    }
+}
+
+fn pixel_seed(seed: u64, i: i32, j: i32) -> u64 {
+    mix_seed(mix_seed(seed, i as u32 as u64), j as u32 as u64)
+}
+
+fn to_unit(bits: u32) -> f64 {
+    (bits as f64 / 4294967296.).min(ONE_MINUS_EPSILON)
+}
+
+/**
+ * Nested uniform scrambling of the bits of `v`: each bit is flipped depending
+ * on a hash of the bits above it.
+ */
+fn owen_scramble(mut v: u32, seed: u64) -> u32 {
+    if seed & 1 != 0 {
+        v ^= 1 << 31;
+    }
+    for b in 1..32 {
+        let mask = !0u32 << (32 - b);
+        if (mix_seed(seed, (v & mask) as u64) >> b) & 1 != 0 {
+            v ^= 1 << (31 - b);
+        }
+    }
+    v
+}
+
+/**
+ * The radical inverse of `index` in `base`, with each digit permuted depending
+ * on the digits before it.
+ */
+fn owen_scrambled_radical_inverse(base: u64, mut index: u64, seed: u64) -> f64 {
+    let inv_base = 1. / base as f64;
+    let mut inv_base_m = 1.;
+    let mut result = 0.;
+    let mut prefix = 0u64;
+    let mut digit_index = 0u64;
+
+    // Keep going after the index runs out of digits, so the scrambled
+    // trailing zeros still fill in the lower digits.
+    while 1. - (base - 1) as f64 * inv_base_m < 1. {
+        let digit = index % base;
+        index /= base;
+
+        let shift = mix_seed(mix_seed(seed, digit_index), prefix) % base;
+        inv_base_m *= inv_base;
+        result += ((digit + shift) % base) as f64 * inv_base_m;
+
+        prefix = prefix.wrapping_mul(base).wrapping_add(digit);
+        digit_index += 1;
+    }
+
+    result.min(ONE_MINUS_EPSILON)
+}
+
+/**
+ * Element `i` of a random permutation of [0, l) picked by `p`, after Kensler's
+ * "Correlated Multi-Jittered Sampling".
+ */
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+
+        if i < l {
+            break;
+        }
+    }
+
+    (i.wrapping_add(p)) % l
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(sampler_type: SamplerType, samples: i32, skip: usize) -> Vec<(f64, f64)> {
+        let mut sampler = sampler_type.create(samples, 3);
+        (0..samples)
+            .map(|index| {
+                sampler.start_pixel_sample(5, 9, index);
+                for _ in 0..skip {
+                    sampler.get_2d();
+                }
+                sampler.get_2d()
+            })
+            .collect()
+    }
+
+    fn one_point_per_cell(points: &[(f64, f64)], n: usize) -> bool {
+        let mut cells = vec![0; n * n];
+        for &(x, y) in points {
+            cells[(y * n as f64) as usize * n + (x * n as f64) as usize] += 1;
+        }
+        cells.iter().all(|&c| c == 1)
+    }
+
+    #[test]
+    fn test_samples_in_unit_square() {
+        for sampler_type in [
+            SamplerType::Independent,
+            SamplerType::Stratified,
+            SamplerType::Halton,
+            SamplerType::Sobol,
+        ] {
+            for skip in [0, 1, 40] {
+                for (x, y) in points(sampler_type, 50, skip) {
+                    assert!((0. ..1.).contains(&x) && (0. ..1.).contains(&y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_stratification() {
+        for skip in [0, 3, 20] {
+            assert!(one_point_per_cell(
+                &points(SamplerType::Stratified, 64, skip),
+                8
+            ));
+            assert!(one_point_per_cell(&points(SamplerType::Sobol, 64, skip), 8));
+        }
+
+        // Halton with bases 2 and 3 stratifies 2^k by 3^m grids.
+        let halton = points(SamplerType::Halton, 6, 0);
+        let mut cells = [0; 6];
+        for (x, y) in halton {
+            cells[(y * 3.) as usize * 2 + (x * 2.) as usize] += 1;
+        }
+        assert_eq!(cells, [1; 6]);
+    }
+
+    #[test]
+    fn test_stratification_without_square_count() {
+        // Ten samples fill a 5 x 2 grid, one per cell.
+        let ten = points(SamplerType::Stratified, 10, 1);
+        let mut cells = [0; 10];
+        for &(x, y) in &ten {
+            cells[(y * 2.) as usize * 5 + (x * 5.) as usize] += 1;
+        }
+        assert_eq!(cells, [1; 10]);
+
+        for samples in [10, 1000] {
+            let mut quadrants = [0; 4];
+            for (x, y) in points(SamplerType::Stratified, samples, 0) {
+                quadrants[(y * 2.) as usize * 2 + (x * 2.) as usize] += 1;
+            }
+            assert!(quadrants.iter().all(|&n| n > 0));
+        }
+    }
+
+    #[test]
+    fn test_permutation_element() {
+        for l in [1, 7, 64, 100] {
+            let mut seen: Vec<u32> = (0..l).map(|i| permutation_element(i, l, 1234)).collect();
+            seen.sort();
+            assert_eq!(seen, (0..l).collect::<Vec<_>>());
+        }
+    }
+}
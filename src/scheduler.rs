@@ -0,0 +1,375 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/**
+ * A rectangle of pixels [x0, x1) x [y0, y1) rendered as one unit of work.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Tile {
+    pub fn pixel_count(&self) -> usize {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x0 >= self.x1 || self.y0 >= self.y1
+    }
+}
+
+/**
+ * A part of the image to render on its own, for iterating on a detail
+ * without rendering the whole frame.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CropWindow {
+    /// The pixels [x0, x1) x [y0, y1) of the full image.
+    Pixels {
+        x0: usize,
+        y0: usize,
+        x1: usize,
+        y1: usize,
+    },
+    /// Fractions of the image width and height measured from the top-left
+    /// corner, widened to whole pixels.
+    Normalized { x0: f64, y0: f64, x1: f64, y1: f64 },
+}
+
+impl CropWindow {
+    /**
+     * The pixels of a `width` x `height` image inside the window, which are
+     * none if it lies outside the image or is inverted.
+     */
+    pub fn region(&self, width: usize, height: usize) -> Tile {
+        match *self {
+            CropWindow::Pixels { x0, y0, x1, y1 } => Tile {
+                x0: x0.min(width),
+                y0: y0.min(height),
+                x1: x1.min(width),
+                y1: y1.min(height),
+            },
+            CropWindow::Normalized { x0, y0, x1, y1 } => {
+                let floor =
+                    |t: f64, size: usize| ((t * size as f64).floor().max(0.) as usize).min(size);
+                let ceil =
+                    |t: f64, size: usize| ((t * size as f64).ceil().max(0.) as usize).min(size);
+                Tile {
+                    x0: floor(x0, width),
+                    y0: floor(y0, height),
+                    x1: ceil(x1, width),
+                    y1: ceil(y1, height),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TileOrder {
+    /// Row by row from the top-left corner.
+    #[default]
+    Scanline,
+    /// Outwards from the center of the image.
+    Spiral,
+    /// Along a Hilbert curve, keeping consecutive tiles close together.
+    Hilbert,
+}
+
+/**
+ * Splits a `width` x `height` image into tiles of at most `tile_size` pixels
+ * square, listed in the order they should be rendered.
+ */
+pub fn generate_tiles(
+    width: usize,
+    height: usize,
+    tile_size: usize,
+    order: TileOrder,
+) -> Vec<Tile> {
+    let tile_size = tile_size.max(1);
+    let nx = width.div_ceil(tile_size);
+    let ny = height.div_ceil(tile_size);
+
+    let tile_at = |tx: usize, ty: usize| Tile {
+        x0: tx * tile_size,
+        y0: ty * tile_size,
+        x1: ((tx + 1) * tile_size).min(width),
+        y1: ((ty + 1) * tile_size).min(height),
+    };
+
+    let coordinates: Vec<(usize, usize)> = match order {
+        TileOrder::Scanline => (0..ny)
+            .flat_map(|ty| (0..nx).map(move |tx| (tx, ty)))
+            .collect(),
+        TileOrder::Spiral => spiral(nx, ny),
+        TileOrder::Hilbert => hilbert(nx, ny),
+    };
+
+    coordinates
+        .into_iter()
+        .map(|(tx, ty)| tile_at(tx, ty))
+        .collect()
+}
+
+/**
+ * Walks a square spiral from the center tile, keeping the tiles inside the grid.
+ */
+fn spiral(nx: usize, ny: usize) -> Vec<(usize, usize)> {
+    let total = nx * ny;
+    let mut coordinates = Vec::with_capacity(total);
+    let (mut x, mut y) = (((nx as i64) - 1) / 2, ((ny as i64) - 1) / 2);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut step = 1;
+    let mut direction = 0;
+
+    let visit = |x: i64, y: i64, coordinates: &mut Vec<(usize, usize)>| {
+        if (0..nx as i64).contains(&x) && (0..ny as i64).contains(&y) {
+            coordinates.push((x as usize, y as usize));
+        }
+    };
+
+    visit(x, y, &mut coordinates);
+    while coordinates.len() < total {
+        // Each step length is walked twice before the spiral widens.
+        for _ in 0..2 {
+            let (dx, dy) = directions[direction];
+            for _ in 0..step {
+                x += dx;
+                y += dy;
+                visit(x, y, &mut coordinates);
+            }
+            direction = (direction + 1) % 4;
+        }
+        step += 1;
+    }
+
+    coordinates
+}
+
+/**
+ * Visits a Hilbert curve over the smallest power-of-two grid covering the
+ * tiles, keeping the tiles inside the grid.
+ */
+fn hilbert(nx: usize, ny: usize) -> Vec<(usize, usize)> {
+    let n = nx.max(ny).next_power_of_two();
+
+    (0..n * n)
+        .map(|d| {
+            // Convert the distance along the curve to grid coordinates.
+            let (mut x, mut y) = (0, 0);
+            let mut t = d;
+            let mut s = 1;
+            while s < n {
+                let rx = 1 & (t / 2);
+                let ry = 1 & (t ^ rx);
+                if ry == 0 {
+                    if rx == 1 {
+                        x = s - 1 - x;
+                        y = s - 1 - y;
+                    }
+                    std::mem::swap(&mut x, &mut y);
+                }
+                x += s * rx;
+                y += s * ry;
+                t /= 4;
+                s *= 2;
+            }
+            (x, y)
+        })
+        .filter(|&(x, y)| x < nx && y < ny)
+        .collect()
+}
+
+/**
+ * A snapshot of how far a render has come, handed to the progress callback
+ * after every finished tile.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    pub completed_tiles: usize,
+    pub total_tiles: usize,
+    pub elapsed: Duration,
+    pub eta: Option<Duration>,
+    pub samples_per_second: f64,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f64 {
+        if self.total_tiles == 0 {
+            return 1.;
+        }
+        self.completed_tiles as f64 / self.total_tiles as f64
+    }
+}
+
+/**
+ * A shared flag to stop a render early. Tiles that have not started by then
+ * are skipped and the ones in flight stop after their current pixel.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+pub type ProgressCallback = dyn Fn(&Progress) + Send + Sync;
+
+/**
+ * Hooks an embedding application passes to `Camera::render_with` to follow
+ * and stop a render.
+ */
+#[derive(Clone, Default)]
+pub struct RenderControl {
+    pub progress: Option<Arc<ProgressCallback>>,
+    pub cancellation: CancellationToken,
+}
+
+impl RenderControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /**
+     * Prints the progress to stderr on a single line.
+     */
+    pub fn stderr() -> Self {
+        Self::new().on_progress(|progress| {
+            let eta = progress
+                .eta
+                .map_or(String::from("--"), |eta| format!("{:.1?}", eta));
+            eprint!(
+                "\rTiles: {}/{} ({:.0}%), ETA {}, {:.0} samples/s      ",
+                progress.completed_tiles,
+                progress.total_tiles,
+                100. * progress.fraction(),
+                eta,
+                progress.samples_per_second
+            );
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_covers_image(tiles: &[Tile], width: usize, height: usize) {
+        let mut covered = vec![0; width * height];
+        for tile in tiles {
+            for y in tile.y0..tile.y1 {
+                for x in tile.x0..tile.x1 {
+                    covered[y * width + x] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&c| c == 1));
+    }
+
+    #[test]
+    fn test_tile_orders_cover_image() {
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            for (width, height) in [(100, 37), (16, 16), (1, 90)] {
+                let tiles = generate_tiles(width, height, 16, order);
+                assert_covers_image(&tiles, width, height);
+            }
+        }
+    }
+
+    #[test]
+    fn test_spiral_starts_in_center() {
+        let tiles = generate_tiles(80, 80, 16, TileOrder::Spiral);
+        assert_eq!(
+            tiles[0],
+            Tile {
+                x0: 32,
+                y0: 32,
+                x1: 48,
+                y1: 48
+            }
+        );
+    }
+
+    #[test]
+    fn test_hilbert_steps_to_neighbours() {
+        let tiles = generate_tiles(64, 64, 8, TileOrder::Hilbert);
+        for pair in tiles.windows(2) {
+            let dx = pair[0].x0.abs_diff(pair[1].x0);
+            let dy = pair[0].y0.abs_diff(pair[1].y0);
+            assert_eq!(dx + dy, 8);
+        }
+    }
+
+    #[test]
+    fn test_crop_window_region() {
+        let pixels = CropWindow::Pixels {
+            x0: 10,
+            y0: 20,
+            x1: 500,
+            y1: 40,
+        };
+        assert_eq!(
+            pixels.region(100, 50),
+            Tile {
+                x0: 10,
+                y0: 20,
+                x1: 100,
+                y1: 40
+            }
+        );
+
+        let normalized = CropWindow::Normalized {
+            x0: 0.25,
+            y0: 0.5,
+            x1: 0.51,
+            y1: 1.,
+        };
+        assert_eq!(
+            normalized.region(100, 50),
+            Tile {
+                x0: 25,
+                y0: 25,
+                x1: 51,
+                y1: 50
+            }
+        );
+
+        let outside = CropWindow::Pixels {
+            x0: 200,
+            y0: 0,
+            x1: 300,
+            y1: 50,
+        };
+        assert!(outside.region(100, 50).is_empty());
+    }
+}
@@ -1,10 +1,14 @@
-use ray_tracing::PI;
+use crate::{INFINITY, PI};
 
 use crate::aabb::*;
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{object_id, HitRecord, Hittable};
+use crate::hittable_list::HittableList;
 use crate::interval::Interval;
 use crate::material::Material;
+use crate::onb::Onb;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::stats::{self, Primitive};
 use crate::vec3::{Point3, Vec3};
 use std::sync::Arc;
 
@@ -18,10 +22,11 @@ pub struct Sphere {
     is_moving: bool,
     center_vec: Vec3,
     bbox: Aabb,
+    id: u64,
 }
 
 impl Sphere {
-    pub(crate) fn new(center: Point3, radius: f64, mat: Arc<dyn Material + Send>) -> Self {
+    pub fn new(center: Point3, radius: f64, mat: Arc<dyn Material + Send>) -> Self {
         let rvec = Vec3::new(radius, radius, radius);
 
         Self {
@@ -31,10 +36,11 @@ impl Sphere {
             is_moving: false,
             center_vec: Vec3::default(),
             bbox: Aabb::new_from_points(center - rvec, center + rvec),
+            id: object_id(&[center[0], center[1], center[2], radius]),
         }
     }
 
-    pub(crate) fn new_moving(
+    pub fn new_moving(
         center: Point3,
         center2: Point3,
         radius: f64,
@@ -50,6 +56,9 @@ impl Sphere {
             is_moving: true,
             center_vec: center2 - center,
             bbox: Aabb::aabb(box1, box2),
+            id: object_id(&[
+                center[0], center[1], center[2], center2[0], center2[1], center2[2], radius,
+            ]),
         }
     }
 
@@ -57,6 +66,19 @@ impl Sphere {
         self.center1 + time * self.center_vec
     }
 
+    /**
+     * A direction around the z axis, uniform over the cone subtended by a
+     * sphere of `radius` at `distance_squared` along it.
+     */
+    fn random_to_sphere(radius: f64, distance_squared: f64, u: (f64, f64)) -> Vec3 {
+        let cos_theta_max = (1. - radius * radius / distance_squared).max(0.).sqrt();
+        let z = 1. + u.1 * (cos_theta_max - 1.);
+        let phi = 2. * PI * u.0;
+        let r = (1. - z * z).max(0.).sqrt();
+
+        Vec3::new(phi.cos() * r, phi.sin() * r, z)
+    }
+
     pub(crate) fn get_sphere_uv(p: Point3, rec: &mut HitRecord) {
         let theta = (-p.y()).acos();
         let phi = (-p.z()).atan2(p.x()) + PI;
@@ -68,6 +90,7 @@ impl Sphere {
 
 impl Hittable for Sphere {
     fn hit(&self, r: Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        stats::intersection_test(Primitive::Sphere);
         let center: Point3 = if self.is_moving {
             self.center(r.time())
         } else {
@@ -104,6 +127,7 @@ impl Hittable for Sphere {
         rec.set_face_normal(r, outward_normal);
         Self::get_sphere_uv(outward_normal, rec);
         rec.mat = self.mat.clone();
+        rec.object_id = self.id;
 
         true
     }
@@ -111,4 +135,56 @@ impl Hittable for Sphere {
     fn bounding_box(&self) -> Aabb {
         self.bbox
     }
+
+    /**
+     * Directions are sampled uniformly over the cone the sphere fills as seen
+     * from `origin`. Moving spheres are sampled where they are at time 0.
+     */
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        let mut rec = HitRecord::default();
+        if !self.hit(
+            Ray::new(origin, direction),
+            Interval::new(0.001, INFINITY),
+            &mut rec,
+        ) {
+            return 0.;
+        }
+
+        let distance_squared = (self.center1 - origin).length_squared();
+        if distance_squared <= self.radius * self.radius {
+            // Seen from inside, the sphere covers every direction.
+            return 1. / (4. * PI);
+        }
+        let cos_theta_max = (1. - self.radius * self.radius / distance_squared)
+            .max(0.)
+            .sqrt();
+        let solid_angle = 2. * PI * (1. - cos_theta_max);
+
+        1. / solid_angle
+    }
+
+    fn random(&self, origin: Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        let direction = self.center1 - origin;
+        let distance_squared = direction.length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return Vec3::sample_unit_vector(sampler.get_2d());
+        }
+        let uvw = Onb::new(direction);
+
+        uvw.transform(Self::random_to_sphere(
+            self.radius,
+            distance_squared,
+            sampler.get_2d(),
+        ))
+    }
+
+    /**
+     * Moving spheres are left out: light sampling has no shutter time to
+     * find them at, so they are only reached by the BSDF samples.
+     */
+    fn collect_lights(&self, lights: &mut HittableList) {
+        if self.mat.is_emissive() && !self.is_moving {
+            lights.add(Box::new(self.clone()));
+        }
+    }
 }
@@ -0,0 +1,502 @@
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Paths with more bounces than this are counted in the last bucket.
+const MAX_BOUNCES: usize = 64;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// The number of renders currently tracing their samples for a heatmap.
+static TRACING: AtomicUsize = AtomicUsize::new(0);
+/// The counters of the running threads.
+static THREADS: Mutex<Vec<Arc<Counters>>> = Mutex::new(Vec::new());
+/// What the threads that have exited counted. Only written while holding the
+/// `THREADS` lock, so a snapshot never sees a thread's counts twice.
+static RETIRED: Counters = Counters::new();
+static TIMINGS: Mutex<[Duration; Phase::ALL.len()]> =
+    Mutex::new([Duration::ZERO; Phase::ALL.len()]);
+
+thread_local! {
+    static COUNTERS: ThreadCounters = {
+        let counters = Arc::new(Counters::new());
+        THREADS.lock().unwrap().push(counters.clone());
+        ThreadCounters(counters)
+    };
+    static PHASES: RefCell<Vec<(Phase, Instant, Duration)>> = const { RefCell::new(Vec::new()) };
+    static TRACE: Cell<Trace> = const { Cell::new(Trace { bvh_nodes: 0, intersection_tests: 0, bounces: 0 }) };
+}
+
+/**
+ * The work done for the camera sample being traced on this thread.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Trace {
+    pub bvh_nodes: u64,
+    pub intersection_tests: u64,
+    /// The longest path of the sample.
+    pub bounces: u64,
+}
+
+/**
+ * Keeps the per-sample traces updated while it is alive, independently of
+ * whether statistics are enabled.
+ */
+pub(crate) struct Tracing;
+
+impl Tracing {
+    pub fn start() -> Self {
+        TRACING.fetch_add(1, Ordering::Relaxed);
+        Tracing
+    }
+}
+
+impl Drop for Tracing {
+    fn drop(&mut self) {
+        TRACING.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) fn start_trace() {
+    TRACE.with(|trace| trace.set(Trace::default()));
+}
+
+pub(crate) fn trace() -> Trace {
+    TRACE.with(|trace| trace.get())
+}
+
+fn traced(update: impl FnOnce(&mut Trace)) {
+    if TRACING.load(Ordering::Relaxed) > 0 {
+        TRACE.with(|trace| {
+            let mut value = trace.get();
+            update(&mut value);
+            trace.set(value);
+        });
+    }
+}
+
+/**
+ * The counters of one thread. Only that thread writes to them, so they are
+ * never contended; a snapshot adds up the counters of all threads.
+ */
+struct Counters {
+    camera_rays: AtomicU64,
+    secondary_rays: AtomicU64,
+    bounces: [AtomicU64; MAX_BOUNCES + 1],
+    bvh_nodes: AtomicU64,
+    intersection_tests: [AtomicU64; Primitive::ALL.len()],
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Self {
+            camera_rays: AtomicU64::new(0),
+            secondary_rays: AtomicU64::new(0),
+            bounces: [const { AtomicU64::new(0) }; MAX_BOUNCES + 1],
+            bvh_nodes: AtomicU64::new(0),
+            intersection_tests: [const { AtomicU64::new(0) }; Primitive::ALL.len()],
+        }
+    }
+
+    fn all(&self) -> impl Iterator<Item = &AtomicU64> {
+        [&self.camera_rays, &self.secondary_rays, &self.bvh_nodes]
+            .into_iter()
+            .chain(&self.bounces)
+            .chain(&self.intersection_tests)
+    }
+
+    fn reset(&self) {
+        for counter in self.all() {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn add_to(&self, total: &Counters) {
+        for (counter, total) in self.all().zip(total.all()) {
+            total.fetch_add(counter.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+}
+
+/**
+ * Owns the counters of a thread and hands them over to `RETIRED` when the
+ * thread exits, so short-lived threads do not pile up in `THREADS`.
+ */
+struct ThreadCounters(Arc<Counters>);
+
+impl Drop for ThreadCounters {
+    fn drop(&mut self) {
+        let mut threads = THREADS.lock().unwrap();
+        self.0.add_to(&RETIRED);
+        threads.retain(|counters| !Arc::ptr_eq(counters, &self.0));
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Primitive {
+    Sphere,
+    Quad,
+}
+
+impl Primitive {
+    pub const ALL: [Primitive; 2] = [Primitive::Sphere, Primitive::Quad];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Primitive::Sphere => "sphere",
+            Primitive::Quad => "quad",
+        }
+    }
+}
+
+/**
+ * The parts of a run that are timed. Phases can nest, for example a BVH built
+ * while building the scene, and every phase only counts the time not spent
+ * in the phases nested in it.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    SceneBuild,
+    BvhBuild,
+    Render,
+    Output,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 4] = [
+        Phase::SceneBuild,
+        Phase::BvhBuild,
+        Phase::Render,
+        Phase::Output,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::SceneBuild => "scene_build",
+            Phase::BvhBuild => "bvh_build",
+            Phase::Render => "render",
+            Phase::Output => "output",
+        }
+    }
+}
+
+/**
+ * Starts collecting statistics. Collection is off by default, which leaves
+ * only a flag check on the hot paths.
+ */
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/**
+ * Clears everything collected so far.
+ */
+pub fn reset() {
+    let threads = THREADS.lock().unwrap();
+    for counters in threads.iter() {
+        counters.reset();
+    }
+    RETIRED.reset();
+    *TIMINGS.lock().unwrap() = Default::default();
+}
+
+fn count(add: impl FnOnce(&Counters)) {
+    if is_enabled() {
+        COUNTERS.with(|counters| add(&counters.0));
+    }
+}
+
+pub(crate) fn camera_ray() {
+    count(|c| {
+        c.camera_rays.fetch_add(1, Ordering::Relaxed);
+    });
+}
+
+pub(crate) fn secondary_ray() {
+    count(|c| {
+        c.secondary_rays.fetch_add(1, Ordering::Relaxed);
+    });
+}
+
+/**
+ * Records a finished path that scattered `bounces` times.
+ */
+pub(crate) fn path(bounces: usize) {
+    count(|c| {
+        c.bounces[bounces.min(MAX_BOUNCES)].fetch_add(1, Ordering::Relaxed);
+    });
+    traced(|trace| trace.bounces = trace.bounces.max(bounces as u64));
+}
+
+pub(crate) fn bvh_node() {
+    count(|c| {
+        c.bvh_nodes.fetch_add(1, Ordering::Relaxed);
+    });
+    traced(|trace| trace.bvh_nodes += 1);
+}
+
+pub(crate) fn intersection_test(primitive: Primitive) {
+    count(|c| {
+        c.intersection_tests[primitive as usize].fetch_add(1, Ordering::Relaxed);
+    });
+    traced(|trace| trace.intersection_tests += 1);
+}
+
+/**
+ * Runs `f` and adds the time it took to `phase`.
+ */
+pub fn time<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    if !is_enabled() {
+        return f();
+    }
+
+    PHASES.with(|phases| {
+        phases
+            .borrow_mut()
+            .push((phase, Instant::now(), Duration::ZERO))
+    });
+    let _timer = PhaseTimer;
+
+    f()
+}
+
+/**
+ * Ends the innermost phase of the thread when dropped, so a phase that panics
+ * is closed as well.
+ */
+struct PhaseTimer;
+
+impl Drop for PhaseTimer {
+    fn drop(&mut self) {
+        PHASES.with(|phases| {
+            let mut phases = phases.borrow_mut();
+            let (phase, start, nested) = phases.pop().unwrap();
+            let elapsed = start.elapsed();
+            TIMINGS.lock().unwrap()[phase as usize] += elapsed.saturating_sub(nested);
+            if let Some(parent) = phases.last_mut() {
+                parent.2 += elapsed;
+            }
+        });
+    }
+}
+
+/**
+ * Everything collected since statistics were enabled or last reset.
+ */
+pub fn snapshot() -> Statistics {
+    let mut stats = Statistics {
+        camera_rays: 0,
+        secondary_rays: 0,
+        bounces: vec![0; MAX_BOUNCES + 1],
+        bvh_nodes_visited: 0,
+        intersection_tests: Primitive::ALL.map(|primitive| (primitive, 0)).to_vec(),
+        timings: Phase::ALL
+            .iter()
+            .zip(TIMINGS.lock().unwrap().iter())
+            .map(|(&phase, &duration)| (phase, duration))
+            .collect(),
+    };
+
+    let threads = THREADS.lock().unwrap();
+    for counters in threads.iter().map(|counters| &**counters).chain([&RETIRED]) {
+        stats.camera_rays += counters.camera_rays.load(Ordering::Relaxed);
+        stats.secondary_rays += counters.secondary_rays.load(Ordering::Relaxed);
+        stats.bvh_nodes_visited += counters.bvh_nodes.load(Ordering::Relaxed);
+        for (total, counter) in stats.bounces.iter_mut().zip(&counters.bounces) {
+            *total += counter.load(Ordering::Relaxed);
+        }
+        for ((_, total), counter) in stats
+            .intersection_tests
+            .iter_mut()
+            .zip(&counters.intersection_tests)
+        {
+            *total += counter.load(Ordering::Relaxed);
+        }
+    }
+
+    let used = stats
+        .bounces
+        .iter()
+        .rposition(|&n| n > 0)
+        .map_or(0, |k| k + 1);
+    stats.bounces.truncate(used);
+
+    stats
+}
+
+/**
+ * A summary of where a render spent its work. Formats as a table with
+ * `Display`, or as JSON with `to_json`.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statistics {
+    pub camera_rays: u64,
+    pub secondary_rays: u64,
+    /// The number of paths that scattered as many times as the index, with
+    /// the last possible index also holding all longer paths.
+    pub bounces: Vec<u64>,
+    pub bvh_nodes_visited: u64,
+    pub intersection_tests: Vec<(Primitive, u64)>,
+    pub timings: Vec<(Phase, Duration)>,
+}
+
+impl Statistics {
+    pub fn rays(&self) -> u64 {
+        self.camera_rays + self.secondary_rays
+    }
+
+    pub fn to_json(&self) -> String {
+        let list = |items: Vec<String>| items.join(", ");
+
+        format!(
+            "{{\"camera_rays\": {}, \"secondary_rays\": {}, \"bounces\": [{}], \
+             \"bvh_nodes_visited\": {}, \"intersection_tests\": {{{}}}, \
+             \"timings\": {{{}}}}}",
+            self.camera_rays,
+            self.secondary_rays,
+            list(self.bounces.iter().map(|n| n.to_string()).collect()),
+            self.bvh_nodes_visited,
+            list(
+                self.intersection_tests
+                    .iter()
+                    .map(|(primitive, n)| format!("\"{}\": {}", primitive.name(), n))
+                    .collect()
+            ),
+            list(
+                self.timings
+                    .iter()
+                    .map(|(phase, duration)| format!(
+                        "\"{}\": {}",
+                        phase.name(),
+                        duration.as_secs_f64()
+                    ))
+                    .collect()
+            ),
+        )
+    }
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let per_ray = |n: u64| n as f64 / self.rays().max(1) as f64;
+
+        writeln!(f, "{:<24}{:>16}", "camera rays", self.camera_rays)?;
+        writeln!(f, "{:<24}{:>16}", "secondary rays", self.secondary_rays)?;
+        writeln!(
+            f,
+            "{:<24}{:>16}{:>12.2} / ray",
+            "BVH nodes visited",
+            self.bvh_nodes_visited,
+            per_ray(self.bvh_nodes_visited)
+        )?;
+        for (primitive, n) in &self.intersection_tests {
+            writeln!(
+                f,
+                "{:<24}{:>16}{:>12.2} / ray",
+                format!("{} tests", primitive.name()),
+                n,
+                per_ray(*n)
+            )?;
+        }
+
+        let paths: u64 = self.bounces.iter().sum();
+        writeln!(f, "bounces per path")?;
+        for (bounces, &n) in self.bounces.iter().enumerate() {
+            let label = if bounces == MAX_BOUNCES {
+                format!("  {}+", bounces)
+            } else {
+                format!("  {}", bounces)
+            };
+            writeln!(
+                f,
+                "{:<24}{:>16}{:>11.1}%",
+                label,
+                n,
+                100. * n as f64 / paths.max(1) as f64
+            )?;
+        }
+
+        writeln!(f, "timings")?;
+        for (phase, duration) in &self.timings {
+            writeln!(f, "  {:<22}{:>16.2?}", phase.name(), duration)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * Restores whether statistics were enabled when the test ends, even if it
+     * fails.
+     */
+    struct RestoreEnabled(bool);
+
+    impl Drop for RestoreEnabled {
+        fn drop(&mut self) {
+            if !self.0 {
+                disable();
+            }
+        }
+    }
+
+    #[test]
+    fn test_nested_phases_and_counters() {
+        let _restore = RestoreEnabled(is_enabled());
+        enable();
+        reset();
+
+        let start = Instant::now();
+        time(Phase::SceneBuild, || {
+            std::thread::sleep(Duration::from_millis(20));
+            time(Phase::BvhBuild, || {
+                std::thread::sleep(Duration::from_millis(20))
+            });
+        });
+        let total = start.elapsed();
+        std::thread::spawn(|| {
+            camera_ray();
+            path(0);
+            intersection_test(Primitive::Quad);
+        })
+        .join()
+        .unwrap();
+        secondary_ray();
+        path(2);
+        bvh_node();
+
+        let stats = snapshot();
+        let timing = |phase: Phase| stats.timings[phase as usize].1;
+        assert!(timing(Phase::SceneBuild) >= Duration::from_millis(20));
+        assert!(timing(Phase::SceneBuild) <= total - Duration::from_millis(20));
+
+        // Other tests may be rendering at the same time, so only check lower
+        // bounds for the rest.
+        assert!(timing(Phase::BvhBuild) >= Duration::from_millis(20));
+        assert!(stats.camera_rays >= 1 && stats.secondary_rays >= 1);
+        assert!(stats.bounces.len() >= 3 && stats.bounces[2] >= 1);
+        assert!(stats.intersection_tests[Primitive::Quad as usize].1 >= 1);
+
+        let json = stats.to_json();
+        assert!(json.starts_with("{\"camera_rays\": "));
+        assert!(json.contains("\"intersection_tests\": {\"sphere\": "));
+        assert!(stats.to_string().contains("bounces per path"));
+
+        // A phase that panics is still closed.
+        let panicked = std::panic::catch_unwind(|| time(Phase::Output, || panic!("failed")));
+        assert!(panicked.is_err());
+        assert!(PHASES.with(|phases| phases.borrow().is_empty()));
+    }
+}
@@ -0,0 +1,41 @@
+use crate::color::Color;
+use crate::framebuffer::Framebuffer;
+use crate::hittable_list::HittableList;
+use crate::material::{DiffuseLight, Material};
+use crate::quad::Quad;
+use crate::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/**
+ * A floor lit by a square light one unit above it, with sides of twice
+ * `half_size`, which lights the floor and nothing else.
+ */
+pub(crate) fn lit_floor(floor: Arc<dyn Material + Send>, half_size: f64) -> HittableList {
+    let mut world = HittableList::default();
+    let light = Arc::new(DiffuseLight::new_with_color(Color::new(4., 4., 4.)));
+    world.add(Box::new(Quad::new(
+        Point3::new(-10., 0., -10.),
+        Vec3::new(20., 0., 0.),
+        Vec3::new(0., 0., 20.),
+        floor,
+    )));
+    world.add(Box::new(Quad::new(
+        Point3::new(-half_size, 1., -half_size),
+        Vec3::new(2. * half_size, 0., 0.),
+        Vec3::new(0., 0., 2. * half_size),
+        light,
+    )));
+
+    world
+}
+
+/**
+ * The mean color of the pixels of `image`.
+ */
+pub(crate) fn mean(image: &Framebuffer) -> Color {
+    let sum = image
+        .pixels()
+        .iter()
+        .fold(Color::default(), |sum, &pixel| sum + pixel);
+    sum / image.pixels().len() as f64
+}
@@ -0,0 +1,204 @@
+use crate::color::{linear_to_srgb, luminance, Color};
+use crate::interval::Interval;
+use std::sync::Arc;
+
+/**
+ * Maps linear scene radiance to linear display values, nominally in [0, 1].
+ */
+pub trait ToneMapper: Sync + Send {
+    fn tone_map(&self, c: Color) -> Color;
+}
+
+/**
+ * No curve at all, values above 1 are clipped when the image is encoded.
+ */
+#[derive(Clone, Copy, Default)]
+pub struct Clamp;
+
+impl ToneMapper for Clamp {
+    fn tone_map(&self, c: Color) -> Color {
+        c
+    }
+}
+
+/**
+ * Reinhard's global operator applied to luminance, L / (1 + L).
+ */
+#[derive(Clone, Copy, Default)]
+pub struct Reinhard;
+
+impl ToneMapper for Reinhard {
+    fn tone_map(&self, c: Color) -> Color {
+        let l = luminance(c);
+        if l <= 0. {
+            return Color::default();
+        }
+
+        c * (1. / (1. + l))
+    }
+}
+
+/**
+ * Reinhard's extended operator, mapping the `white` luminance to 1.
+ */
+#[derive(Clone, Copy)]
+pub struct ReinhardExtended {
+    white: f64,
+}
+
+impl ReinhardExtended {
+    pub fn new(white: f64) -> Self {
+        Self { white }
+    }
+}
+
+impl ToneMapper for ReinhardExtended {
+    fn tone_map(&self, c: Color) -> Color {
+        let l = luminance(c);
+        if l <= 0. {
+            return Color::default();
+        }
+
+        let mapped = l * (1. + l / (self.white * self.white)) / (1. + l);
+        c * (mapped / l)
+    }
+}
+
+/**
+ * Krzysztof Narkowicz's fit of the ACES filmic curve.
+ */
+#[derive(Clone, Copy, Default)]
+pub struct Aces;
+
+impl ToneMapper for Aces {
+    fn tone_map(&self, c: Color) -> Color {
+        let curve = |x: f64| {
+            let x = x.max(0.);
+            Interval::new(0., 1.).clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14))
+        };
+
+        Color::new(curve(c.x()), curve(c.y()), curve(c.z()))
+    }
+}
+
+/**
+ * John Hable's filmic curve from Uncharted 2. The input is doubled by the
+ * curve's exposure bias before `white` is mapped to 1, so the radiance that
+ * comes out white is `white / 2`.
+ */
+#[derive(Clone, Copy)]
+pub struct Hable {
+    white: f64,
+}
+
+impl Hable {
+    const EXPOSURE_BIAS: f64 = 2.;
+
+    pub fn new(white: f64) -> Self {
+        Self { white }
+    }
+
+    fn partial(x: f64) -> f64 {
+        let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+        ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+    }
+}
+
+impl Default for Hable {
+    fn default() -> Self {
+        Self::new(11.2)
+    }
+}
+
+impl ToneMapper for Hable {
+    fn tone_map(&self, c: Color) -> Color {
+        let white_scale = 1. / Self::partial(self.white);
+        let curve = |x: f64| Self::partial(Self::EXPOSURE_BIAS * x.max(0.)) * white_scale;
+
+        Color::new(curve(c.x()), curve(c.y()), curve(c.z()))
+    }
+}
+
+/**
+ * Turns linear radiance into display-encoded sRGB: scale by the exposure in
+ * stops, apply the tone mapper, then the sRGB transfer function.
+ */
+#[derive(Clone)]
+pub struct DisplayTransform {
+    pub exposure: f64,
+    pub tone_mapper: Arc<dyn ToneMapper>,
+}
+
+impl DisplayTransform {
+    pub fn new(exposure: f64, tone_mapper: Arc<dyn ToneMapper>) -> Self {
+        Self {
+            exposure,
+            tone_mapper,
+        }
+    }
+
+    pub fn apply(&self, c: Color) -> Color {
+        let exposed = 2f64.powf(self.exposure) * c;
+        let mapped = self.tone_mapper.tone_map(exposed);
+
+        let intensity = Interval::new(0., 1.);
+        Color::new(
+            linear_to_srgb(intensity.clamp(mapped.x())),
+            linear_to_srgb(intensity.clamp(mapped.y())),
+            linear_to_srgb(intensity.clamp(mapped.z())),
+        )
+    }
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self::new(0., Arc::new(Clamp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{a} != {b}");
+    }
+
+    #[test]
+    fn test_srgb_transfer() {
+        assert_close(linear_to_srgb(0.), 0.);
+        assert_close(linear_to_srgb(1.), 1.);
+        assert_close(linear_to_srgb(0.5), 0.735357);
+        // Both segments of the curve meet at the breakpoint.
+        assert_close(linear_to_srgb(0.0031308), 12.92 * 0.0031308);
+    }
+
+    #[test]
+    fn test_operators_stay_in_range() {
+        let operators: [Arc<dyn ToneMapper>; 5] = [
+            Arc::new(Clamp),
+            Arc::new(Reinhard),
+            Arc::new(ReinhardExtended::new(4.)),
+            Arc::new(Aces),
+            Arc::new(Hable::default()),
+        ];
+
+        for operator in operators {
+            let display = DisplayTransform::new(1., operator);
+            let mut previous = -1.;
+            for i in 0..100 {
+                let value = display.apply(Color::new(1., 1., 1.) * (i as f64 * 0.5)).x();
+                assert!((0. ..=1.).contains(&value));
+                assert!(value >= previous);
+                previous = value;
+            }
+        }
+    }
+
+    #[test]
+    fn test_white_points() {
+        let white = Color::new(4., 4., 4.);
+        assert_close(ReinhardExtended::new(4.).tone_map(white).x(), 1.);
+        assert_close(Hable::new(4.).tone_map(white / 2.).y(), 1.);
+    }
+}
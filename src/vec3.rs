@@ -1,5 +1,4 @@
-use ray_tracing::{random_double, random_double_r};
-use std::cmp::min;
+use crate::{random_double, random_double_r, PI};
 use std::ops::{Add, AddAssign, Div, Index, IndexMut, Mul, MulAssign, Neg, Sub};
 
 #[derive(Clone, Copy, PartialEq, Default)]
@@ -86,6 +85,43 @@ impl Vec3 {
         }
     }
 
+    /**
+     * Maps a uniform 2D sample to a uniformly distributed point in the unit disk.
+     */
+    pub fn sample_in_unit_disk(u: (f64, f64)) -> Vec3 {
+        let r = u.0.sqrt();
+        let theta = 2. * PI * u.1;
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.)
+    }
+
+    /**
+     * Maps a uniform 2D sample to a uniformly distributed unit vector.
+     */
+    pub fn sample_unit_vector(u: (f64, f64)) -> Vec3 {
+        let z = 1. - 2. * u.0;
+        let r = (1. - z * z).max(0.).sqrt();
+        let phi = 2. * PI * u.1;
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    /**
+     * Maps a uniform 2D sample to a direction around the z axis distributed
+     * with density cos(theta) / pi.
+     */
+    pub fn sample_cosine_direction(u: (f64, f64)) -> Vec3 {
+        let phi = 2. * PI * u.0;
+        let r = u.1.sqrt();
+        Vec3::new(r * phi.cos(), r * phi.sin(), (1. - u.1).sqrt())
+    }
+
+    /**
+     * Maps uniform samples for the direction and the radius to a uniformly
+     * distributed point in the unit sphere.
+     */
+    pub fn sample_in_unit_sphere(u: (f64, f64), r: f64) -> Vec3 {
+        r.cbrt() * Vec3::sample_unit_vector(u)
+    }
+
     pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
         v - 2f64 * v.dot(n) * n
     }